@@ -0,0 +1,89 @@
+//! Address-sharded table of the recorded blocks.
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::{metadata::MetaHashMap, Block};
+
+/// Number of shards, must be a power of two.
+const SHARDS: usize = 64;
+
+/// Table of the live recorded blocks, keyed by address.
+///
+/// The table is split into independently locked shards so that allocations
+/// and frees on different threads rarely contend on the same lock.
+pub(crate) struct BlockTable {
+    shards: [Mutex<MetaHashMap<usize, Block>>; SHARDS],
+}
+
+impl Default for BlockTable {
+    fn default() -> Self {
+        Self {
+            shards: std::array::from_fn(|_| Mutex::default()),
+        }
+    }
+}
+
+impl BlockTable {
+    #[inline]
+    fn shard_index(ptr: usize) -> usize {
+        // fibonacci hashing, the low bits of addresses are mostly alignment.
+        (ptr.wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize)
+            >> (usize::BITS - SHARDS.trailing_zeros()))
+            & (SHARDS - 1)
+    }
+
+    #[inline]
+    fn shard(&self, ptr: usize) -> MutexGuard<'_, MetaHashMap<usize, Block>> {
+        lock(&self.shards[Self::shard_index(ptr)])
+    }
+
+    /// Insert a block, returns the block previously recorded at the same address.
+    pub(crate) fn insert(&self, ptr: usize, block: Block) -> Option<Block> {
+        self.shard(ptr).insert(ptr, block)
+    }
+
+    /// Remove and returns the block recorded at `ptr`.
+    pub(crate) fn remove(&self, ptr: usize) -> Option<Block> {
+        self.shard(ptr).remove(&ptr)
+    }
+
+    /// Updates the block recorded at `ptr` with `f`.
+    pub(crate) fn update<F, R>(&self, ptr: usize, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut Block) -> R,
+    {
+        self.shard(ptr).get_mut(&ptr).map(f)
+    }
+
+    /// Locks every shard, returns a consistent view of the table.
+    pub(crate) fn lock_all(&self) -> BlockTableGuard<'_> {
+        BlockTableGuard {
+            shards: self.shards.iter().map(lock).collect(),
+        }
+    }
+}
+
+/// A locked view of the whole [`BlockTable`].
+pub(crate) struct BlockTableGuard<'a> {
+    shards: Vec<MutexGuard<'a, MetaHashMap<usize, Block>>>,
+}
+
+impl BlockTableGuard<'_> {
+    /// Iterate over every recorded block.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&usize, &Block)> {
+        self.shards.iter().flat_map(|shard| shard.iter())
+    }
+
+    /// Drop every recorded block.
+    pub(crate) fn clear(&mut self) {
+        for shard in self.shards.iter_mut() {
+            shard.clear();
+        }
+    }
+}
+
+/// Locks `mutex`, a panic while holding a profiler lock does not invalidate its data.
+#[inline]
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,166 @@
+//! Live memory budget, see [`set_memory_budget`].
+//!
+//! ```no_run
+//! use hala_pprof_memory::{set_memory_budget, MemoryBudget, PprofAlloc};
+//!
+//! #[global_allocator]
+//! static ALLOC: PprofAlloc = PprofAlloc::new(10).with_sample_rate(512 * 1024);
+//!
+//! fn over_budget(live_bytes: usize) {
+//!     // must not take locks held around allocations.
+//! }
+//!
+//! fn main() {
+//!     // the container is killed at 2.5 GiB.
+//!     set_memory_budget(
+//!         MemoryBudget::new(2 << 30)
+//!             .with_hysteresis(256 << 20)
+//!             .with_callback(over_budget),
+//!     );
+//! }
+//! ```
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Mutex,
+};
+
+use crate::blocks::lock;
+
+/// A threshold on the bytes allocated through [`PprofAlloc`](crate::PprofAlloc),
+/// with the actions run when it is crossed.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryBudget {
+    limit: usize,
+    hysteresis: Option<usize>,
+    callback: Option<fn(usize)>,
+    snapshot: bool,
+}
+
+impl MemoryBudget {
+    /// Create a budget crossed when the live bytes exceed `limit`, with no action.
+    pub const fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            hysteresis: None,
+            callback: None,
+            snapshot: false,
+        }
+    }
+
+    /// The budget fires again only after the live bytes drop `hysteresis` bytes
+    /// below the limit, a tenth of the limit by default.
+    pub const fn with_hysteresis(mut self, hysteresis: usize) -> Self {
+        self.hysteresis = Some(hysteresis);
+        self
+    }
+
+    /// Call `callback` with the live bytes when the budget is crossed.
+    ///
+    /// The callback runs inside the allocation that crossed the budget: it may allocate,
+    /// those allocations are not recorded, but it must not take locks that may be held
+    /// around allocations, and should return quickly.
+    pub const fn with_callback(mut self, callback: fn(usize)) -> Self {
+        self.callback = Some(callback);
+        self
+    }
+
+    /// Whether to write a [`snapshot`](crate::snapshot) when the budget is crossed, false by default.
+    ///
+    /// The snapshot is written synchronously inside the allocation that crossed the budget:
+    /// that thread stalls for the whole symbolization and write, and the other threads
+    /// block on their next sampled allocation. Errors writing it are ignored.
+    /// Requires the `report` feature, without it this does nothing.
+    pub const fn with_snapshot(mut self, snapshot: bool) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// The live bytes below which the budget fires again.
+    fn rearm_below(&self) -> usize {
+        self.limit
+            .saturating_sub(self.hysteresis.unwrap_or(self.limit / 10))
+    }
+}
+
+/// The limit of the current budget, `usize::MAX` if none.
+static LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// The live bytes below which the budget fires again.
+static REARM_BELOW: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether crossing the limit fires the budget.
+static ARMED: AtomicBool = AtomicBool::new(false);
+
+static BUDGET: Mutex<Option<MemoryBudget>> = Mutex::new(None);
+
+/// Sets the live memory budget, replacing the previous one.
+///
+/// The budget applies to every byte allocated through [`PprofAlloc`](crate::PprofAlloc),
+/// not only the recorded ones, and is checked even when the profiler is stopped.
+/// If the budget is already crossed, it fires on the next allocation.
+pub fn set_memory_budget(budget: MemoryBudget) {
+    let mut current = lock(&BUDGET);
+
+    *current = Some(budget);
+
+    REARM_BELOW.store(budget.rearm_below(), Ordering::Relaxed);
+    ARMED.store(true, Ordering::Relaxed);
+    LIMIT.store(budget.limit, Ordering::Release);
+}
+
+/// Removes the live memory budget.
+pub fn clear_memory_budget() {
+    let mut current = lock(&BUDGET);
+
+    *current = None;
+
+    LIMIT.store(usize::MAX, Ordering::Release);
+    ARMED.store(false, Ordering::Relaxed);
+}
+
+/// Checks the budget after an allocation raised the live bytes to `live`.
+#[inline]
+pub(crate) fn on_grow(live: usize) {
+    if live > LIMIT.load(Ordering::Relaxed) {
+        fire(live);
+    }
+}
+
+/// Checks the budget after a free lowered the live bytes to `live`.
+#[inline]
+pub(crate) fn on_shrink(live: usize) {
+    if live < REARM_BELOW.load(Ordering::Relaxed)
+        && LIMIT.load(Ordering::Relaxed) != usize::MAX
+        && !ARMED.load(Ordering::Relaxed)
+    {
+        ARMED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Called under the reentrancy guard of the allocator, the allocations of the callback
+/// and of the snapshot are neither recorded nor checked against the budget.
+#[cold]
+fn fire(live: usize) {
+    // only the first allocation crossing the limit fires.
+    if ARMED
+        .compare_exchange(true, false, Ordering::Relaxed, Ordering::Relaxed)
+        .is_err()
+    {
+        return;
+    }
+
+    let Some(budget) = *lock(&BUDGET) else {
+        return;
+    };
+
+    if let Some(callback) = budget.callback {
+        callback(live);
+    }
+
+    // nothing is printed, stderr must not be locked inside the allocator.
+    #[cfg(feature = "report")]
+    if budget.snapshot {
+        _ = crate::snapshot();
+    }
+}
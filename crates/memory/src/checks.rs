@@ -0,0 +1,366 @@
+//! Invalid-free and layout-mismatch detection, see [`PprofAlloc::with_free_checks`](crate::PprofAlloc::with_free_checks).
+//!
+//! ```no_run
+//! use hala_pprof_memory::{memory_errors, PprofAlloc};
+//!
+//! #[global_allocator]
+//! static ALLOC: PprofAlloc = PprofAlloc::new(32).with_free_checks(true);
+//!
+//! fn main() {
+//!     // exercise the unsafe code...
+//!
+//!     for error in memory_errors() {
+//!         eprintln!("{}", error);
+//!     }
+//! }
+//! ```
+
+use std::{alloc::Layout, fmt, sync::Mutex};
+
+use crate::{
+    blocks::lock,
+    helper::{symbolize_lock, Reentrancy},
+    initialized_heap_profiler,
+    metadata::{MetaAlloc, MetaBox, MetaHashMap, MetaVec},
+    profiler::frames_to_symbols,
+};
+
+/// Number of freed blocks remembered to detect double frees.
+///
+/// They are only returned to the inner allocator when evicted, so their addresses
+/// cannot be reused while a second free would still be caught.
+const QUARANTINE: usize = 4096;
+
+/// Number of errors kept, the following ones are only counted.
+const MAX_ERRORS: usize = 1024;
+
+/// The kind of a [`MemoryError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryErrorKind {
+    /// A recorded block was freed, or reallocated, after its free.
+    DoubleFree,
+    /// A pointer that was never recorded was freed or reallocated.
+    UnknownFree,
+    /// A block was freed or reallocated with a `Layout` other than the one it was allocated with.
+    LayoutMismatch,
+}
+
+/// An invalid deallocation, detected by [`PprofAlloc::with_free_checks`](crate::PprofAlloc::with_free_checks).
+#[derive(Debug, Clone)]
+pub struct MemoryError {
+    /// What is wrong with the free.
+    pub kind: MemoryErrorKind,
+    /// The freed pointer.
+    pub address: usize,
+    /// The layout passed to `dealloc` or `realloc`.
+    pub layout: Layout,
+    /// The layout of the block when it was allocated, if it was recorded.
+    pub recorded_layout: Option<Layout>,
+    /// The allocation stack of the block, empty if it was not recorded.
+    pub alloc_backtrace: Vec<String>,
+    /// The stack of the invalid free.
+    pub free_backtrace: Vec<String>,
+    /// The stack of the first free of a [`DoubleFree`](MemoryErrorKind::DoubleFree), empty otherwise.
+    pub previous_free_backtrace: Vec<String>,
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} of 0x{:x}, size {} align {}",
+            self.kind,
+            self.address,
+            self.layout.size(),
+            self.layout.align()
+        )?;
+
+        if let Some(recorded) = self.recorded_layout {
+            write!(
+                f,
+                ", allocated with size {} align {}",
+                recorded.size(),
+                recorded.align()
+            )?;
+        }
+
+        for (title, backtrace) in [
+            ("freed at", &self.free_backtrace),
+            ("previously freed at", &self.previous_free_backtrace),
+            ("allocated at", &self.alloc_backtrace),
+        ] {
+            if backtrace.is_empty() {
+                continue;
+            }
+
+            write!(f, "\n  {}:", title)?;
+
+            for frame in backtrace {
+                write!(f, "\n    {}", frame)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A recorded block freed recently.
+struct FreedBlock {
+    layout: Layout,
+    /// Whether the block is returned to the inner allocator when evicted,
+    /// false for a block freed with the wrong layout, which is leaked instead.
+    release: bool,
+    alloc_frames: MetaBox<[usize]>,
+    free_frames: MetaBox<[usize]>,
+}
+
+/// A detected error, with raw stacks.
+pub(crate) struct RawMemoryError {
+    pub kind: MemoryErrorKind,
+    pub address: usize,
+    pub layout: Layout,
+    pub recorded_layout: Option<Layout>,
+    pub alloc_frames: MetaBox<[usize]>,
+    pub free_frames: MetaBox<[usize]>,
+    pub previous_free_frames: MetaBox<[usize]>,
+}
+
+struct Quarantine {
+    blocks: MetaHashMap<usize, FreedBlock>,
+    /// Addresses of `blocks`, oldest first from `next`.
+    order: MetaVec<usize>,
+    next: usize,
+}
+
+/// Allocations made by the profiler itself, which it cannot record.
+type Hidden = MetaHashMap<usize, ()>;
+
+struct Errors {
+    errors: MetaVec<RawMemoryError>,
+    dropped: usize,
+}
+
+/// The freed blocks and detected errors of the checking mode.
+pub(crate) struct FreeChecker {
+    quarantine: Mutex<Quarantine>,
+    hidden: Mutex<Hidden>,
+    errors: Mutex<Errors>,
+}
+
+impl Default for FreeChecker {
+    fn default() -> Self {
+        Self {
+            quarantine: Mutex::new(Quarantine {
+                blocks: Default::default(),
+                order: MetaVec::new_in(MetaAlloc),
+                next: 0,
+            }),
+            hidden: Default::default(),
+            errors: Mutex::new(Errors {
+                errors: MetaVec::new_in(MetaAlloc),
+                dropped: 0,
+            }),
+        }
+    }
+}
+
+impl FreeChecker {
+    /// Remembers the block freed at `ptr`, evicting the oldest one when full.
+    ///
+    /// Returns the address and layout of the evicted block, if the caller must free it now.
+    pub(crate) fn quarantine(
+        &self,
+        ptr: usize,
+        layout: Layout,
+        release: bool,
+        alloc_frames: &[usize],
+        free_frames: &[usize],
+    ) -> Option<(usize, Layout)> {
+        let block = FreedBlock {
+            layout,
+            release,
+            alloc_frames: MetaBox::from(alloc_frames),
+            free_frames: MetaBox::from(free_frames),
+        };
+
+        let mut quarantine = lock(&self.quarantine);
+
+        let Quarantine {
+            blocks,
+            order,
+            next,
+        } = &mut *quarantine;
+
+        if blocks.insert(ptr, block).is_some() {
+            return None;
+        }
+
+        if order.len() < QUARANTINE {
+            order.push(ptr);
+            return None;
+        }
+
+        let evicted = std::mem::replace(&mut order[*next], ptr);
+
+        *next = (*next + 1) % QUARANTINE;
+
+        // the evicted address may have been reused and freed again since.
+        if evicted == ptr {
+            return None;
+        }
+
+        blocks
+            .remove(&evicted)
+            .filter(|block| block.release)
+            .map(|block| (evicted, block.layout))
+    }
+
+    /// Forgets the freed block at `ptr`, the address was allocated again.
+    pub(crate) fn forget(&self, ptr: usize) {
+        lock(&self.quarantine).blocks.remove(&ptr);
+    }
+
+    /// Remembers the allocation at `ptr`, made by the profiler while it could not record it,
+    /// e.g. the results of [`memory_errors`], so that its free is not reported.
+    ///
+    /// Called from inside the allocator, this only allocates from the metadata allocator.
+    pub(crate) fn hide(&self, ptr: usize) {
+        lock(&self.hidden).insert(ptr, ());
+    }
+
+    /// Forgets the allocation at `ptr` made by the profiler, returns false if there is none.
+    pub(crate) fn unhide(&self, ptr: usize) -> bool {
+        lock(&self.hidden).remove(&ptr).is_some()
+    }
+
+    /// Records the free of `ptr` that matches no live block, a double free if `ptr` is
+    /// in quarantine, an unknown free if `report_unknown` is set.
+    ///
+    /// Returns true if an error is recorded.
+    pub(crate) fn invalid_free(
+        &self,
+        ptr: usize,
+        layout: Layout,
+        free_frames: &[usize],
+        report_unknown: bool,
+    ) -> bool {
+        let error = match lock(&self.quarantine).blocks.get(&ptr) {
+            Some(freed) => RawMemoryError {
+                kind: MemoryErrorKind::DoubleFree,
+                address: ptr,
+                layout,
+                recorded_layout: Some(freed.layout),
+                alloc_frames: MetaBox::from(&*freed.alloc_frames),
+                free_frames: MetaBox::from(free_frames),
+                previous_free_frames: MetaBox::from(&*freed.free_frames),
+            },
+            None if report_unknown => RawMemoryError {
+                kind: MemoryErrorKind::UnknownFree,
+                address: ptr,
+                layout,
+                recorded_layout: None,
+                alloc_frames: MetaBox::from(&[][..]),
+                free_frames: MetaBox::from(free_frames),
+                previous_free_frames: MetaBox::from(&[][..]),
+            },
+            None => return false,
+        };
+
+        self.report(error);
+
+        true
+    }
+
+    /// Records an error.
+    pub(crate) fn report(&self, error: RawMemoryError) {
+        let mut errors = lock(&self.errors);
+
+        if errors.errors.len() < MAX_ERRORS {
+            errors.errors.push(error);
+        } else {
+            errors.dropped += 1;
+        }
+    }
+
+    /// Returns the kept errors, symbolized.
+    fn errors(&self) -> Vec<MemoryError> {
+        // copy the errors out first, symbolization is slow and must not block frees.
+        let raw = lock(&self.errors)
+            .errors
+            .iter()
+            .map(|error| {
+                (
+                    error.kind,
+                    error.address,
+                    error.layout,
+                    error.recorded_layout,
+                    [
+                        error.alloc_frames.to_vec(),
+                        error.free_frames.to_vec(),
+                        error.previous_free_frames.to_vec(),
+                    ],
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let _symbolizer = symbolize_lock();
+
+        raw.into_iter()
+            .map(|(kind, address, layout, recorded_layout, frames)| {
+                let [alloc, free, previous_free] = frames.map(|frames| symbolize(&frames));
+
+                MemoryError {
+                    kind,
+                    address,
+                    layout,
+                    recorded_layout,
+                    alloc_backtrace: alloc,
+                    free_backtrace: free,
+                    previous_free_backtrace: previous_free,
+                }
+            })
+            .collect()
+    }
+}
+
+fn symbolize(frames: &[usize]) -> Vec<String> {
+    frames_to_symbols(frames)
+        .into_iter()
+        .flatten()
+        .map(|symbol| {
+            if symbol.is_unresolved() {
+                format!("0x{:x}", symbol.ip)
+            } else {
+                format!("{} at {}:{}", symbol.name, symbol.file_name, symbol.line_no)
+            }
+        })
+        .collect()
+}
+
+/// Returns the invalid frees detected so far, at most the first 1024.
+///
+/// Empty unless the allocator is created with [`PprofAlloc::with_free_checks`](crate::PprofAlloc::with_free_checks).
+pub fn memory_errors() -> Vec<MemoryError> {
+    let Some(profiler) = initialized_heap_profiler() else {
+        return vec![];
+    };
+
+    let _guard = Reentrancy::new();
+
+    profiler.checker().errors()
+}
+
+/// Returns the number of invalid frees detected, including those beyond the ones kept by [`memory_errors`].
+pub fn memory_error_count() -> usize {
+    let Some(profiler) = initialized_heap_profiler() else {
+        return 0;
+    };
+
+    let _guard = Reentrancy::new();
+
+    let checker = profiler.checker();
+
+    let errors = lock(&checker.errors);
+
+    errors.errors.len() + errors.dropped
+}
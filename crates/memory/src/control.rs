@@ -0,0 +1,74 @@
+//! Runtime control of the heap profiler.
+//!
+//! These functions do nothing until a [`PprofAlloc`](crate::PprofAlloc) has
+//! served its first allocation, which creates the global profiler.
+//!
+//! ```no_run
+//! use hala_pprof_memory::{snapshot, start, stop, PprofAlloc};
+//!
+//! #[global_allocator]
+//! static ALLOC: PprofAlloc = PprofAlloc::new(10).with_autostart(false);
+//!
+//! fn main() {
+//!     // an incident begins.
+//!     start();
+//!     // ...
+//!     stop();
+//!     snapshot().unwrap();
+//! }
+//! ```
+
+use crate::{helper::Reentrancy, initialized_heap_profiler, ProfilerState};
+
+/// Drops everything recorded so far and starts recording new allocations.
+pub fn start() {
+    if let Some(profiler) = initialized_heap_profiler() {
+        let _guard = Reentrancy::new();
+
+        profiler.set_state(ProfilerState::Stopped);
+        profiler.reset();
+        profiler.set_state(ProfilerState::Running);
+    }
+}
+
+/// Stops recording new allocations.
+///
+/// Blocks recorded before the call are kept, and their frees are still matched,
+/// so a later [`snapshot`](crate::snapshot) reports what is left of them.
+pub fn stop() {
+    if let Some(profiler) = initialized_heap_profiler() {
+        profiler.set_state(ProfilerState::Stopped);
+    }
+}
+
+/// Suspends recording of new allocations until [`resume`] is called.
+///
+/// Unlike [`stop`] followed by [`start`], pausing keeps the recorded data.
+pub fn pause() {
+    if let Some(profiler) = initialized_heap_profiler() {
+        profiler.transit(ProfilerState::Running, ProfilerState::Paused);
+    }
+}
+
+/// Resumes recording after a [`pause`], does nothing if the profiler is not paused.
+pub fn resume() {
+    if let Some(profiler) = initialized_heap_profiler() {
+        profiler.transit(ProfilerState::Paused, ProfilerState::Running);
+    }
+}
+
+/// Drops every recorded block and cumulative statistic, the recording state is left unchanged.
+pub fn reset() {
+    if let Some(profiler) = initialized_heap_profiler() {
+        let _guard = Reentrancy::new();
+
+        profiler.reset();
+    }
+}
+
+/// Returns true if new allocations are currently recorded.
+pub fn is_running() -> bool {
+    initialized_heap_profiler()
+        .map(|profiler| profiler.state() == ProfilerState::Running)
+        .unwrap_or(false)
+}
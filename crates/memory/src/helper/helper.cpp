@@ -1,10 +1,56 @@
 #include <thread>
 #include <mutex>
+#include <atomic>
+#include <cstdint>
+#include <cstring>
+#include <cstdlib>
+#include <functional>
+
+#if defined(_WIN32)
+#include <windows.h>
+#else
+#include <pthread.h>
+#include <sys/mman.h>
+#include <unistd.h>
+#endif
+
+#if defined(__linux__)
+#include <sys/syscall.h>
+#endif
 
 thread_local static int COUNTER = 0;
 
+/// @brief Per-thread state of the allocation sampler.
+struct sampler_state
+{
+    int64_t bytes_until_sample;
+    uint64_t rng;
+};
+
+thread_local static sampler_state SAMPLER = {0, 0};
+
+/// @brief Id of the calling thread's current label set, zero is the empty set.
+thread_local static uint32_t LABELS = 0;
+
+/// @brief Bounds of a thread's stack, `low == high` if they are not known.
+struct stack_bounds
+{
+    uintptr_t low;
+    uintptr_t high;
+    bool queried;
+};
+
+thread_local static stack_bounds STACK = {0, 0, false};
+
+/// @brief Index of the calling thread, assigned on first use, `UINT32_MAX` until then.
+thread_local static uint32_t THREAD_INDEX = UINT32_MAX;
+
+static std::atomic<uint32_t> next_thread_index(0);
+
 static std::recursive_mutex backtrace_mutex;
 
+static std::recursive_mutex symbolize_mutex;
+
 extern "C"
 {
 
@@ -26,6 +72,167 @@ extern "C"
         return COUNTER;
     }
 
+    /// @brief Returns the calling thread's sampler state.
+    sampler_state *sampler_state_get()
+    {
+        return &SAMPLER;
+    }
+
+    /// @brief Returns the calling thread's current label set id.
+    uint32_t labels_get()
+    {
+        return LABELS;
+    }
+
+    /// @brief Sets the calling thread's current label set id, returns the previous one.
+    uint32_t labels_set(uint32_t labels)
+    {
+        uint32_t previous = LABELS;
+        LABELS = labels;
+        return previous;
+    }
+
+    /// @brief Returns the index of the calling thread, in the order threads first call this.
+    uint32_t helper_thread_index()
+    {
+        if (THREAD_INDEX == UINT32_MAX)
+        {
+            THREAD_INDEX = next_thread_index.fetch_add(1, std::memory_order_relaxed);
+        }
+
+        return THREAD_INDEX;
+    }
+
+    /// @brief Returns the operating system id of the calling thread.
+    uint64_t helper_thread_id()
+    {
+#if defined(__linux__)
+        return (uint64_t)syscall(SYS_gettid);
+#elif defined(__APPLE__)
+        uint64_t id = 0;
+        pthread_threadid_np(NULL, &id);
+        return id;
+#elif defined(_WIN32)
+        return (uint64_t)GetCurrentThreadId();
+#else
+        return (uint64_t)std::hash<std::thread::id>{}(std::this_thread::get_id());
+#endif
+    }
+
+    /// @brief Copies the name of the calling thread into `buf`.
+    /// @return The length of the name, zero if the thread has no name.
+    size_t helper_thread_name(char *buf, size_t len)
+    {
+        if (len == 0)
+        {
+            return 0;
+        }
+
+        buf[0] = 0;
+
+#if defined(__linux__) || defined(__APPLE__)
+        if (pthread_getname_np(pthread_self(), buf, len) != 0)
+        {
+            return 0;
+        }
+
+        buf[len - 1] = 0;
+#endif
+
+        return strlen(buf);
+    }
+
+    /// @brief Gets the bounds of the calling thread's stack, queried once per thread.
+    /// @return False if the bounds are not known on this target.
+    bool helper_stack_bounds(uintptr_t *low, uintptr_t *high)
+    {
+        if (!STACK.queried)
+        {
+            STACK.queried = true;
+
+#if defined(__linux__)
+            pthread_attr_t attr;
+
+            if (pthread_getattr_np(pthread_self(), &attr) == 0)
+            {
+                void *addr = NULL;
+                size_t size = 0;
+
+                if (pthread_attr_getstack(&attr, &addr, &size) == 0)
+                {
+                    STACK.low = (uintptr_t)addr;
+                    STACK.high = (uintptr_t)addr + size;
+                }
+
+                pthread_attr_destroy(&attr);
+            }
+#elif defined(__APPLE__)
+            pthread_t self = pthread_self();
+
+            STACK.high = (uintptr_t)pthread_get_stackaddr_np(self);
+            STACK.low = STACK.high - pthread_get_stacksize_np(self);
+#endif
+        }
+
+        *low = STACK.low;
+        *high = STACK.high;
+
+        return STACK.low < STACK.high;
+    }
+
+    /// @brief Copies the host name into `buf`.
+    /// @return The length of the name, zero if it is not available.
+    size_t helper_hostname(char *buf, size_t len)
+    {
+        if (len == 0)
+        {
+            return 0;
+        }
+
+        buf[0] = 0;
+
+#if defined(_WIN32)
+        DWORD size = (DWORD)len;
+
+        if (!GetComputerNameA(buf, &size))
+        {
+            return 0;
+        }
+#else
+        if (gethostname(buf, len) != 0)
+        {
+            return 0;
+        }
+#endif
+
+        buf[len - 1] = 0;
+
+        return strlen(buf);
+    }
+
+    /// @brief Maps `len` bytes of zeroed, readable and writable pages.
+    /// @return The address of the pages, NULL on failure.
+    void *helper_map_pages(size_t len)
+    {
+#if defined(_WIN32)
+        return VirtualAlloc(NULL, len, MEM_RESERVE | MEM_COMMIT, PAGE_READWRITE);
+#else
+        void *addr = mmap(NULL, len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+
+        return addr == MAP_FAILED ? NULL : addr;
+#endif
+    }
+
+    /// @brief Unmaps the `len` bytes of pages mapped at `addr` by `helper_map_pages`.
+    void helper_unmap_pages(void *addr, size_t len)
+    {
+#if defined(_WIN32)
+        VirtualFree(addr, 0, MEM_RELEASE);
+#else
+        munmap(addr, len);
+#endif
+    }
+
     /// @brief locks the backtrace mutex, blocks if the mutex is not available
     void backtrace_mutex_lock()
     {
@@ -38,6 +245,25 @@ extern "C"
         backtrace_mutex.unlock();
     }
 
+    /// @brief locks the symbolize mutex, blocks if the mutex is not available
+    void symbolize_mutex_lock()
+    {
+        symbolize_mutex.lock();
+    }
+
+    /// @brief unlocks the symbolize mutex.
+    void symbolize_mutex_unlock()
+    {
+        symbolize_mutex.unlock();
+    }
+
+    /// @brief Registers `hook` to be called at process exit.
+    /// @return Zero on success.
+    int helper_atexit(void (*hook)(void))
+    {
+        return std::atexit(hook);
+    }
+
     /// @brief unlocks the backtrace mutex.
     void helper_println(const char *message)
     {
@@ -1,4 +1,7 @@
-use std::ffi::c_int;
+use std::{
+    ffi::{c_char, c_int, c_void},
+    ptr::NonNull,
+};
 
 extern "C" {
     /// Reentrancy guard counter plus 1.
@@ -7,12 +10,53 @@ extern "C" {
     /// Reentrancy guard counter sub 1.
     fn reentrancy_guard_counter_sub() -> c_int;
 
+    /// Returns the calling thread's sampler state.
+    fn sampler_state_get() -> *mut SamplerState;
+
+    /// Returns the calling thread's current label set id.
+    fn labels_get() -> u32;
+
+    /// Sets the calling thread's current label set id, returns the previous one.
+    fn labels_set(labels: u32) -> u32;
+
+    /// Returns the index of the calling thread, in the order threads first call it.
+    fn helper_thread_index() -> u32;
+
+    /// Returns the operating system id of the calling thread.
+    fn helper_thread_id() -> u64;
+
+    /// Copies the name of the calling thread into `buf`, returns the length of the name.
+    fn helper_thread_name(buf: *mut c_char, len: usize) -> usize;
+
+    /// Gets the bounds of the calling thread's stack, returns false if they are not known.
+    fn helper_stack_bounds(low: *mut usize, high: *mut usize) -> bool;
+
+    /// Copies the host name into `buf`, returns the length of the name.
+    #[cfg(feature = "report")]
+    fn helper_hostname(buf: *mut c_char, len: usize) -> usize;
+
+    /// Maps `len` bytes of zeroed pages, returns null on failure.
+    fn helper_map_pages(len: usize) -> *mut c_void;
+
+    /// Unmaps the `len` bytes of pages mapped at `addr` by `helper_map_pages`.
+    fn helper_unmap_pages(addr: *mut c_void, len: usize);
+
     /// locks the backtrace mutex, blocks if the mutex is not available
     fn backtrace_mutex_lock();
 
     /// unlocks the backtrace mutex.
     fn backtrace_mutex_unlock();
 
+    /// locks the symbolize mutex, blocks if the mutex is not available
+    fn symbolize_mutex_lock();
+
+    /// unlocks the symbolize mutex.
+    fn symbolize_mutex_unlock();
+
+    /// Registers `hook` to be called at process exit, returns zero on success.
+    #[allow(unused)]
+    fn helper_atexit(hook: extern "C" fn()) -> c_int;
+
     #[allow(unused)]
     pub(crate) fn helper_println(message: *mut i8);
 
@@ -66,3 +110,114 @@ impl Drop for BacktraceGuard {
         unsafe { backtrace_mutex_unlock() }
     }
 }
+
+/// Symbolizer mutex guard.
+#[must_use = "The guard drop immediately"]
+pub(crate) struct SymbolizeGuard;
+
+/// Synchronize the symbolization api calls and returns `locker` guard.
+///
+/// Distinct from [`backtrace_lock`], stack captures are not blocked while reports are symbolized.
+#[inline]
+pub(crate) fn symbolize_lock() -> SymbolizeGuard {
+    unsafe {
+        symbolize_mutex_lock();
+    }
+
+    SymbolizeGuard
+}
+
+impl Drop for SymbolizeGuard {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { symbolize_mutex_unlock() }
+    }
+}
+
+/// Per-thread state of the allocation sampler, see `helper.cpp`.
+#[repr(C)]
+pub(crate) struct SamplerState {
+    /// Remaining bytes before the next sampled allocation.
+    pub bytes_until_sample: i64,
+    /// Random generator state, zero means not yet seeded.
+    pub rng: u64,
+}
+
+/// Returns the calling thread's sampler state.
+///
+/// The state is stored in a C++ `thread_local`, which unlike rust's
+/// `thread_local!` never allocates nor registers destructors.
+#[inline]
+pub(crate) fn sampler_state() -> &'static mut SamplerState {
+    unsafe { &mut *sampler_state_get() }
+}
+
+/// Returns the operating system id of the calling thread.
+#[inline]
+pub(crate) fn thread_id() -> u64 {
+    unsafe { helper_thread_id() }
+}
+
+/// Returns a small index of the calling thread, assigned in the order threads first call this,
+/// never reused.
+#[inline]
+pub(crate) fn thread_index() -> usize {
+    unsafe { helper_thread_index() as usize }
+}
+
+/// Copies the name of the calling thread into `buf`, returns the name.
+///
+/// Unlike [`std::thread::current`], this works while thread-locals are being destroyed.
+pub(crate) fn thread_name(buf: &mut [u8]) -> &[u8] {
+    let len = unsafe { helper_thread_name(buf.as_mut_ptr() as *mut c_char, buf.len()) };
+
+    &buf[..len.min(buf.len())]
+}
+
+/// Returns the `(low, high)` bounds of the calling thread's stack, `None` if they are not known.
+#[allow(unused)]
+pub(crate) fn stack_bounds() -> Option<(usize, usize)> {
+    let (mut low, mut high) = (0, 0);
+
+    unsafe { helper_stack_bounds(&mut low, &mut high) }.then_some((low, high))
+}
+
+/// Copies the host name into `buf`, returns the name, empty if it is not available.
+#[cfg(feature = "report")]
+pub(crate) fn hostname(buf: &mut [u8]) -> &[u8] {
+    let len = unsafe { helper_hostname(buf.as_mut_ptr() as *mut c_char, buf.len()) };
+
+    &buf[..len.min(buf.len())]
+}
+
+/// Maps `len` bytes of zeroed, page-aligned memory straight from the operating system.
+pub(crate) fn map_pages(len: usize) -> Option<NonNull<u8>> {
+    NonNull::new(unsafe { helper_map_pages(len) } as *mut u8)
+}
+
+/// Returns the `len` bytes of pages mapped at `ptr` to the operating system.
+///
+/// # Safety
+///
+/// `ptr` and `len` must be the ones of a [`map_pages`] call.
+pub(crate) unsafe fn unmap_pages(ptr: NonNull<u8>, len: usize) {
+    helper_unmap_pages(ptr.as_ptr() as *mut c_void, len)
+}
+
+/// Registers `hook` to be called at process exit, returns false on failure.
+#[allow(unused)]
+pub(crate) fn at_exit(hook: extern "C" fn()) -> bool {
+    unsafe { helper_atexit(hook) == 0 }
+}
+
+/// Returns the calling thread's current label set id.
+#[inline]
+pub(crate) fn current_labels() -> u32 {
+    unsafe { labels_get() }
+}
+
+/// Sets the calling thread's current label set id, returns the previous one.
+#[inline]
+pub(crate) fn set_current_labels(labels: u32) -> u32 {
+    unsafe { labels_set(labels) }
+}
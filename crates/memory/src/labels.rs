@@ -0,0 +1,170 @@
+//! User-defined profiler labels, the equivalent of go's `pprof.Do`.
+//!
+//! Labels set on a thread are attached to every allocation that thread records,
+//! and emitted as sample labels, so profiles can be filtered with `pprof -tagfocus`.
+//!
+//! ```no_run
+//! use hala_pprof_memory::{with_labels, PprofAlloc};
+//!
+//! #[global_allocator]
+//! static ALLOC: PprofAlloc = PprofAlloc::new(10);
+//!
+//! fn main() {
+//!     let results = with_labels(&[("endpoint", "/search")], || vec![0u8; 1024]);
+//! }
+//! ```
+
+use std::{marker::PhantomData, sync::Mutex};
+
+use crate::{
+    blocks::lock,
+    helper::{current_labels, set_current_labels, Reentrancy},
+    initialized_heap_profiler,
+    metadata::{MetaBox, MetaHashMap, MetaVec},
+};
+
+/// Identifier of an interned label set, zero is the empty set.
+pub(crate) type LabelSetId = u32;
+
+/// Separates keys and values in an encoded label set.
+const SEPARATOR: char = '\0';
+
+struct LabelTableInner {
+    /// Encoded label sets, indexed by id.
+    sets: MetaVec<MetaBox<str>>,
+    index: MetaHashMap<MetaBox<str>, LabelSetId>,
+}
+
+/// Table of the interned label sets.
+///
+/// Sets are encoded as sorted `key\0value\0` sequences, and never dropped,
+/// since threads and live blocks keep referring to them.
+pub(crate) struct LabelTable {
+    inner: Mutex<LabelTableInner>,
+}
+
+impl Default for LabelTable {
+    fn default() -> Self {
+        let mut sets = MetaVec::new_in(Default::default());
+
+        sets.push(MetaBox::from(""));
+
+        Self {
+            inner: Mutex::new(LabelTableInner {
+                sets,
+                index: Default::default(),
+            }),
+        }
+    }
+}
+
+impl LabelTable {
+    /// Returns the id of the set `base` overridden by `labels`.
+    fn merge(&self, base: LabelSetId, labels: &[(&str, &str)]) -> LabelSetId {
+        let mut merged = self.get(base);
+
+        for (key, value) in labels {
+            // the separator cannot be escaped, such labels would be split apart.
+            if key.contains(SEPARATOR) || value.contains(SEPARATOR) {
+                continue;
+            }
+
+            match merged.iter_mut().find(|(k, _)| k == key) {
+                Some((_, v)) => *v = value.to_string(),
+                None => merged.push((key.to_string(), value.to_string())),
+            }
+        }
+
+        merged.sort();
+
+        let mut encoded = String::new();
+
+        for (key, value) in merged {
+            encoded.push_str(&key);
+            encoded.push(SEPARATOR);
+            encoded.push_str(&value);
+            encoded.push(SEPARATOR);
+        }
+
+        if encoded.is_empty() {
+            return 0;
+        }
+
+        let mut inner = lock(&self.inner);
+
+        if let Some(id) = inner.index.get(encoded.as_str()) {
+            return *id;
+        }
+
+        let id = inner.sets.len() as LabelSetId;
+
+        inner.sets.push(MetaBox::from(encoded.as_str()));
+        inner.index.insert(MetaBox::from(encoded.as_str()), id);
+
+        id
+    }
+
+    /// Returns the labels of the set `id`.
+    pub(crate) fn get(&self, id: LabelSetId) -> Vec<(String, String)> {
+        let inner = lock(&self.inner);
+
+        let Some(encoded) = inner.sets.get(id as usize) else {
+            return vec![];
+        };
+
+        let mut parts = encoded.split_terminator(SEPARATOR);
+
+        let mut labels = vec![];
+
+        while let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+            labels.push((key.to_string(), value.to_string()));
+        }
+
+        labels
+    }
+}
+
+/// Restores the previous labels of the current thread when dropped, see [`set_labels`].
+#[must_use = "The labels are removed when the guard drops"]
+pub struct LabelGuard {
+    previous: LabelSetId,
+    // the labels belong to the thread that set them.
+    _marker: PhantomData<*const ()>,
+}
+
+impl Drop for LabelGuard {
+    fn drop(&mut self) {
+        set_current_labels(self.previous);
+    }
+}
+
+/// Adds `labels` to the current thread until the returned guard drops.
+///
+/// Labels are merged with the ones already set, a key already present takes the new value.
+/// Labels whose key or value contains a `'\0'` are ignored.
+/// Every allocation recorded by the thread in the meantime carries the labels.
+pub fn set_labels(labels: &[(&str, &str)]) -> LabelGuard {
+    let previous = current_labels();
+
+    if let Some(profiler) = initialized_heap_profiler() {
+        // the merge allocates, which must not be recorded as an allocation of the caller.
+        let _guard = Reentrancy::new();
+
+        set_current_labels(profiler.labels().merge(previous, labels));
+    }
+
+    LabelGuard {
+        previous,
+        _marker: PhantomData,
+    }
+}
+
+/// Calls `f` with `labels` added to the current thread, see [`set_labels`].
+pub fn with_labels<F, R>(labels: &[(&str, &str)], f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = set_labels(labels);
+
+    f()
+}
@@ -0,0 +1,98 @@
+//! Leak detection at process exit, see [`PprofAlloc::with_leak_report`](crate::PprofAlloc::with_leak_report).
+//!
+//! Every block still live when the process exits is a leak, except the ones
+//! marked with [`mark_leaked`] or allocated through [`leak`].
+//!
+//! ```no_run
+//! use hala_pprof_memory::{leak, PprofAlloc};
+//!
+//! #[global_allocator]
+//! static ALLOC: PprofAlloc = PprofAlloc::new(10).with_leak_report(true);
+//!
+//! fn main() {
+//!     // lives until exit on purpose, not reported.
+//!     let config: &'static mut Vec<u8> = leak(Box::new(vec![0u8; 1024]));
+//! }
+//! ```
+
+#[cfg(feature = "report")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{helper::Reentrancy, initialized_heap_profiler};
+
+/// Excludes the recorded block at `ptr` from the leak reports.
+///
+/// Returns false if no recorded block starts at `ptr`, e.g. when it was not sampled.
+pub fn mark_leaked<T: ?Sized>(ptr: *const T) -> bool {
+    let Some(profiler) = initialized_heap_profiler() else {
+        return false;
+    };
+
+    let _guard = Reentrancy::new();
+
+    profiler.mark_leaked(ptr as *const u8)
+}
+
+/// [`Box::leak`] `value`, excluding its block from the leak reports.
+///
+/// Only the block of the box is excluded, not the blocks it owns.
+pub fn leak<T>(value: Box<T>) -> &'static mut T {
+    let value = Box::leak(value);
+
+    mark_leaked(value as *const T);
+
+    value
+}
+
+/// Set when the exit hook could not be registered, reported in the comments of the profiles.
+#[cfg(feature = "report")]
+static UNREGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// Registers the exit hook writing the leak report.
+///
+/// Called from inside the allocator, a failure is recorded rather than printed.
+#[cfg(feature = "report")]
+pub(crate) fn register_leak_report() {
+    if !crate::helper::at_exit(report_leaks) {
+        UNREGISTERED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Returns true if the exit hook writing the leak report could not be registered.
+#[cfg(feature = "report")]
+pub(crate) fn leak_report_unregistered() -> bool {
+    UNREGISTERED.load(Ordering::Relaxed)
+}
+
+/// Writes the profile of the leaked blocks like [`snapshot`](crate::snapshot), with the `memory.leaks` prefix.
+#[cfg(feature = "report")]
+extern "C" fn report_leaks() {
+    use crate::{profiler::ProfilerState, report::write_profile, ReportMode};
+
+    let Some(profiler) = initialized_heap_profiler() else {
+        return;
+    };
+
+    // the allocations of the shutdown itself are not leaks.
+    profiler.set_state(ProfilerState::Stopped);
+
+    let _guard = Reentrancy::new();
+
+    let profile = profiler.report(ReportMode::Leaks);
+
+    let (blocks, bytes) = profile
+        .sample
+        .iter()
+        .filter(|sample| sample.value[2] > 0)
+        .fold((0, 0), |(blocks, bytes), sample| {
+            (blocks + sample.value[2], bytes + sample.value[3])
+        });
+
+    if let Err(err) = write_profile("memory.leaks", profile) {
+        eprintln!("memory-profiler: failed to write the leak report, {}", err);
+    }
+
+    if blocks > 0 {
+        eprintln!("memory-profiler: {} leaked blocks, {} bytes", blocks, bytes);
+    }
+}
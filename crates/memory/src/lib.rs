@@ -7,7 +7,7 @@
 //! use hala_pprof_memory::PprofAlloc;
 //!
 //! #[global_allocator]
-//! static ALLOC: PprofAlloc = PprofAlloc(10);
+//! static ALLOC: PprofAlloc = PprofAlloc::new(10);
 //! ```
 //!
 //! `PprofAlloc` does not automatically generate memory profiling reports,
@@ -20,13 +20,13 @@
 //! use hala_pprof_memory::{PprofAlloc,snapshot};
 //!
 //! #[global_allocator]
-//! static ALLOC: PprofAlloc = PprofAlloc(10);
+//! static ALLOC: PprofAlloc = PprofAlloc::new(10);
 //!
 //! fn main() {
 //!     loop {
 //!         // working...
 //!         // generate report.
-//!         snapshot();
+//!         snapshot().unwrap();
 //!     }
 //! }
 //! ```
@@ -34,14 +34,55 @@
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
 mod helper;
-mod proto;
+#[cfg(feature = "report")]
+#[cfg_attr(docsrs, doc(cfg(feature = "report")))]
+pub mod proto;
 
+mod blocks;
+mod metadata;
+pub use metadata::*;
 mod profiler;
 pub use profiler::*;
 
+mod peak;
+mod sampler;
+mod usage;
+pub use usage::{live_bytes, stats, HeapStats};
+mod stacks;
+mod threads;
+pub use threads::{thread_stats, ThreadStats};
+
+mod unwind;
+pub use unwind::{BacktraceUnwinder, FramePointerUnwinder, StackUnwinder};
+
+mod control;
+pub use control::*;
+
+mod labels;
+pub use labels::{set_labels, with_labels, LabelGuard};
+
+mod leaks;
+pub use leaks::{leak, mark_leaked};
+
+mod budget;
+pub use budget::{clear_memory_budget, set_memory_budget, MemoryBudget};
+
+mod checks;
+pub use checks::{memory_error_count, memory_errors, MemoryError, MemoryErrorKind};
+
 #[cfg(feature = "report")]
 #[cfg_attr(docsrs, doc(cfg(feature = "report")))]
 mod report;
 
 #[cfg(feature = "report")]
 pub use report::*;
+
+#[cfg(feature = "report")]
+#[cfg_attr(docsrs, doc(cfg(feature = "report")))]
+mod output;
+
+#[cfg(feature = "report")]
+mod mappings;
+
+#[cfg(feature = "report")]
+pub use output::{parse_profile, read_profile, set_snapshot_config, SnapshotConfig};
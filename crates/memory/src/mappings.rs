@@ -0,0 +1,270 @@
+//! Executable mappings of the process, emitted as the profile `Mapping` table
+//! so that `pprof` can match the binaries and re-symbolize the locations.
+
+/// An executable memory mapping of the process.
+pub(crate) struct Mapping {
+    /// The address of the first byte of the mapping.
+    pub start: u64,
+    /// The address past the last byte of the mapping.
+    pub limit: u64,
+    /// The offset of the mapping in its file.
+    pub offset: u64,
+    /// The mapped file, or a pseudo path such as `[vdso]`.
+    pub path: String,
+    /// The hex encoded GNU build id of the file, empty if it has none.
+    pub build_id: String,
+}
+
+/// Returns the executable mappings of the process sorted by address,
+/// empty on other systems than linux.
+#[cfg(target_os = "linux")]
+pub(crate) fn read_mappings() -> Vec<Mapping> {
+    let Ok(maps) = std::fs::read_to_string("/proc/self/maps") else {
+        return vec![];
+    };
+
+    let mut mappings = parse_maps(&maps);
+
+    mappings.sort_by_key(|mapping| mapping.start);
+
+    // a file is often mapped several times, its build id is read once.
+    let mut build_ids = std::collections::HashMap::new();
+
+    for mapping in &mut mappings {
+        if !mapping.path.starts_with('/') {
+            continue;
+        }
+
+        mapping.build_id = build_ids
+            .entry(mapping.path.clone())
+            .or_insert_with(|| {
+                elf::build_id(&mapping.path)
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default()
+            })
+            .clone();
+    }
+
+    mappings
+}
+
+/// Returns the executable mappings of the process sorted by address,
+/// empty on other systems than linux.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn read_mappings() -> Vec<Mapping> {
+    vec![]
+}
+
+/// Parses the executable file-backed mappings of `/proc/self/maps`, lines like:
+///
+/// `55d0c1a00000-55d0c1b00000 r-xp 00002000 fd:01 1048602   /usr/bin/server`
+#[cfg(target_os = "linux")]
+fn parse_maps(maps: &str) -> Vec<Mapping> {
+    let mut mappings = vec![];
+
+    for line in maps.lines() {
+        let mut fields = line.splitn(6, ' ');
+
+        let (Some(range), Some(perms), Some(offset), Some(_dev), Some(_inode)) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) else {
+            continue;
+        };
+
+        let path = fields.next().unwrap_or_default().trim_start();
+
+        // anonymous mappings, e.g. jit code, have no file to match.
+        if !perms.contains('x') || path.is_empty() {
+            continue;
+        }
+
+        let Some((start, limit)) = range.split_once('-') else {
+            continue;
+        };
+
+        let (Ok(start), Ok(limit), Ok(offset)) = (
+            u64::from_str_radix(start, 16),
+            u64::from_str_radix(limit, 16),
+            u64::from_str_radix(offset, 16),
+        ) else {
+            continue;
+        };
+
+        mappings.push(Mapping {
+            start,
+            limit,
+            offset,
+            path: path.to_owned(),
+            build_id: String::new(),
+        });
+    }
+
+    mappings
+}
+
+/// Just enough ELF parsing to read the GNU build id note.
+#[cfg(target_os = "linux")]
+mod elf {
+    use std::{
+        fs::File,
+        io::{self, Read, Seek, SeekFrom},
+    };
+
+    const PT_NOTE: u32 = 4;
+
+    const NT_GNU_BUILD_ID: u32 = 3;
+
+    /// Note segments larger than this are skipped, the build id note is a few dozen bytes.
+    const MAX_NOTES: u64 = 64 * 1024;
+
+    /// Reads integers of the file's class and byte order.
+    struct Reader {
+        is_64: bool,
+        big_endian: bool,
+    }
+
+    impl Reader {
+        fn u16(&self, buf: &[u8], at: usize) -> u16 {
+            let bytes = [buf[at], buf[at + 1]];
+
+            if self.big_endian {
+                u16::from_be_bytes(bytes)
+            } else {
+                u16::from_le_bytes(bytes)
+            }
+        }
+
+        fn u32(&self, buf: &[u8], at: usize) -> u32 {
+            let bytes = buf[at..at + 4].try_into().unwrap();
+
+            if self.big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            }
+        }
+
+        fn u64(&self, buf: &[u8], at: usize) -> u64 {
+            let bytes = buf[at..at + 8].try_into().unwrap();
+
+            if self.big_endian {
+                u64::from_be_bytes(bytes)
+            } else {
+                u64::from_le_bytes(bytes)
+            }
+        }
+
+        /// Reads an address sized field, at `at64` in 64-bit files and `at32` in 32-bit ones.
+        fn addr(&self, buf: &[u8], at64: usize, at32: usize) -> u64 {
+            if self.is_64 {
+                self.u64(buf, at64)
+            } else {
+                self.u32(buf, at32) as u64
+            }
+        }
+    }
+
+    /// Returns the hex encoded GNU build id of the ELF file at `path`, `None` if it has none.
+    pub(super) fn build_id(path: &str) -> io::Result<Option<String>> {
+        let mut file = File::open(path)?;
+
+        // the 64-bit header, the 32-bit one is shorter.
+        let mut header = [0u8; 64];
+
+        file.read_exact(&mut header)?;
+
+        if header[..4] != *b"\x7fELF" {
+            return Ok(None);
+        }
+
+        let reader = Reader {
+            is_64: header[4] == 2,
+            big_endian: header[5] == 2,
+        };
+
+        let (phoff, phentsize, phnum) = if reader.is_64 {
+            (
+                reader.u64(&header, 32),
+                reader.u16(&header, 54) as usize,
+                reader.u16(&header, 56) as usize,
+            )
+        } else {
+            (
+                reader.u32(&header, 28) as u64,
+                reader.u16(&header, 42) as usize,
+                reader.u16(&header, 44) as usize,
+            )
+        };
+
+        if phentsize < if reader.is_64 { 56 } else { 32 } {
+            return Ok(None);
+        }
+
+        let mut headers = vec![0u8; phentsize * phnum];
+
+        file.seek(SeekFrom::Start(phoff))?;
+        file.read_exact(&mut headers)?;
+
+        for header in headers.chunks_exact(phentsize) {
+            if reader.u32(header, 0) != PT_NOTE {
+                continue;
+            }
+
+            let offset = reader.addr(header, 8, 4);
+            let size = reader.addr(header, 32, 16);
+            let align = reader.addr(header, 48, 28);
+
+            if size > MAX_NOTES {
+                continue;
+            }
+
+            let mut notes = vec![0u8; size as usize];
+
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut notes)?;
+
+            if let Some(build_id) = find_build_id(&reader, &notes, align.max(4) as usize) {
+                return Ok(Some(build_id));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Walks the notes of a `PT_NOTE` segment, whose fields are padded to `align` bytes.
+    fn find_build_id(reader: &Reader, mut notes: &[u8], align: usize) -> Option<String> {
+        let pad = |len: usize| len.div_ceil(align) * align;
+
+        while notes.len() >= 12 {
+            let name_size = reader.u32(notes, 0) as usize;
+            let desc_size = reader.u32(notes, 4) as usize;
+            let kind = reader.u32(notes, 8);
+
+            let name_start = 12;
+            let desc_start = name_start + pad(name_size);
+            let next = desc_start + pad(desc_size);
+
+            if next > notes.len() {
+                return None;
+            }
+
+            if kind == NT_GNU_BUILD_ID && notes[name_start..name_start + name_size] == *b"GNU\0" {
+                return Some(
+                    notes[desc_start..desc_start + desc_size]
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect(),
+                );
+            }
+
+            notes = &notes[next..];
+        }
+
+        None
+    }
+}
@@ -0,0 +1,246 @@
+//! The arena of the profiler's own bookkeeping, pages mapped straight from the
+//! operating system, apart from the profiled heap.
+
+use std::{
+    alloc::Layout,
+    collections::hash_map::DefaultHasher,
+    hash::BuildHasherDefault,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use allocator_api2::alloc::{AllocError, Allocator, System};
+
+use crate::{
+    blocks::lock,
+    helper::{map_pages, unmap_pages},
+};
+
+/// Bytes currently mapped by [`MetaAlloc`].
+static METADATA_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The smallest size class, a free block holds the address of the next one.
+const MIN_CLASS_SHIFT: u32 = 4;
+
+/// Number of size classes, from 16 bytes to 32 KiB, larger blocks are mapped on their own.
+const CLASSES: usize = 12;
+
+/// Bytes mapped at once to refill a size class.
+const SLAB: usize = 64 * 1024;
+
+/// The alignment of mapped pages, a larger alignment is served by the system allocator.
+const PAGE: usize = 4096;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_LIST: Mutex<usize> = Mutex::new(0);
+
+/// Address of the first free block of every size class, zero if there is none.
+static FREE_LISTS: [Mutex<usize>; CLASSES] = [EMPTY_LIST; CLASSES];
+
+/// Returns the size class of `layout`, `None` if its blocks are mapped on their own.
+#[inline]
+fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+
+    let shift = usize::BITS - (size.max(1) - 1).leading_zeros();
+
+    let class = shift.saturating_sub(MIN_CLASS_SHIFT) as usize;
+
+    (class < CLASSES).then_some(class)
+}
+
+/// Returns the bytes mapped for a block of `size` bytes without a size class.
+#[inline]
+fn mapped_len(size: usize) -> usize {
+    size.div_ceil(PAGE) * PAGE
+}
+
+/// Allocator of the profiler metadata: block table, stack table and captured frames.
+///
+/// Metadata never goes through the profiled allocator nor the reentrancy guard, it lives
+/// in pages mapped from the operating system. Small blocks are carved from 64 KiB slabs
+/// into power-of-two size classes, and reused from their free list. Blocks larger than
+/// 32 KiB, e.g. the tables, are mapped on their own and unmapped when freed.
+/// The mapped bytes, free blocks included, are returned by [`profiler_overhead`].
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct MetaAlloc;
+
+impl MetaAlloc {
+    /// Pops a block of the size class `class`, mapping a new slab if there is none.
+    fn pop(class: usize) -> Option<NonNull<u8>> {
+        let size = 1 << (class + MIN_CLASS_SHIFT as usize);
+
+        let mut head = lock(&FREE_LISTS[class]);
+
+        if *head == 0 {
+            let slab = map_pages(SLAB)?;
+
+            METADATA_BYTES.fetch_add(SLAB, Ordering::Relaxed);
+
+            // links every block of the slab, the first one at the head.
+            for offset in (0..SLAB).step_by(size) {
+                let next = if offset + size < SLAB {
+                    slab.as_ptr() as usize + offset + size
+                } else {
+                    0
+                };
+
+                unsafe { (slab.as_ptr().add(offset) as *mut usize).write(next) };
+            }
+
+            *head = slab.as_ptr() as usize;
+        }
+
+        let block = *head as *mut u8;
+
+        *head = unsafe { (block as *const usize).read() };
+
+        NonNull::new(block)
+    }
+
+    /// Pushes the block at `ptr` to the free list of the size class `class`.
+    unsafe fn push(class: usize, ptr: NonNull<u8>) {
+        let mut head = lock(&FREE_LISTS[class]);
+
+        (ptr.as_ptr() as *mut usize).write(*head);
+
+        *head = ptr.as_ptr() as usize;
+    }
+}
+
+unsafe impl Allocator for MetaAlloc {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            let dangling = unsafe { NonNull::new_unchecked(layout.align() as *mut u8) };
+
+            return Ok(NonNull::slice_from_raw_parts(dangling, 0));
+        }
+
+        if layout.align() > PAGE {
+            return System.allocate(layout);
+        }
+
+        let ptr = match size_class(layout) {
+            Some(class) => Self::pop(class),
+            None => {
+                let len = mapped_len(layout.size());
+
+                let ptr = map_pages(len);
+
+                if ptr.is_some() {
+                    METADATA_BYTES.fetch_add(len, Ordering::Relaxed);
+                }
+
+                ptr
+            }
+        };
+
+        ptr.map(|ptr| NonNull::slice_from_raw_parts(ptr, layout.size()))
+            .ok_or(AllocError)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+
+        if layout.align() > PAGE {
+            return System.deallocate(ptr, layout);
+        }
+
+        match size_class(layout) {
+            Some(class) => Self::push(class, ptr),
+            None => {
+                let len = mapped_len(layout.size());
+
+                unmap_pages(ptr, len);
+
+                METADATA_BYTES.fetch_sub(len, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+}
+
+impl MetaAlloc {
+    /// Moves the block at `ptr` to a block of `new_layout`, kept in place
+    /// if both layouts are served by the same block size.
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let in_place = old_layout.size() != 0
+            && new_layout.size() != 0
+            && old_layout.align() <= PAGE
+            && new_layout.align() <= PAGE
+            && match (size_class(old_layout), size_class(new_layout)) {
+                (Some(old), Some(new)) => old == new,
+                (None, None) => mapped_len(old_layout.size()) == mapped_len(new_layout.size()),
+                _ => false,
+            };
+
+        if in_place {
+            return Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()));
+        }
+
+        let new_ptr = self.allocate(new_layout)?;
+
+        std::ptr::copy_nonoverlapping(
+            ptr.as_ptr(),
+            new_ptr.as_ptr() as *mut u8,
+            old_layout.size().min(new_layout.size()),
+        );
+
+        self.deallocate(ptr, old_layout);
+
+        Ok(new_ptr)
+    }
+}
+
+/// A `HashMap` allocated by [`MetaAlloc`].
+pub(crate) type MetaHashMap<K, V> =
+    hashbrown::HashMap<K, V, BuildHasherDefault<DefaultHasher>, MetaAlloc>;
+
+/// A `Vec` allocated by [`MetaAlloc`].
+pub(crate) type MetaVec<T> = allocator_api2::vec::Vec<T, MetaAlloc>;
+
+/// A `Box` allocated by [`MetaAlloc`].
+pub(crate) type MetaBox<T> = allocator_api2::boxed::Box<T, MetaAlloc>;
+
+/// Returns the number of bytes the profiler itself uses for its bookkeeping.
+///
+/// This covers the block table, the interned call stacks and the frames being captured,
+/// the pages mapped by the metadata arena, its free blocks included, which are not
+/// reported in the profiles.
+///
+/// The caches of the symbolizer and the memory used while building a report are not
+/// counted: they go through the profiled allocator, unrecorded, and show in [`stats`](crate::stats).
+pub fn profiler_overhead() -> usize {
+    METADATA_BYTES.load(Ordering::Relaxed)
+}
@@ -0,0 +1,338 @@
+//! Destination, file names and retention of the profiles written by [`snapshot`](crate::snapshot),
+//! see [`set_snapshot_config`].
+//!
+//! ```no_run
+//! use hala_pprof_memory::{set_snapshot_config, snapshot, PprofAlloc, SnapshotConfig};
+//!
+//! #[global_allocator]
+//! static ALLOC: PprofAlloc = PprofAlloc::new(10);
+//!
+//! fn main() {
+//!     set_snapshot_config(
+//!         SnapshotConfig::new()
+//!             .with_directory("/var/lib/myservice/profiles")
+//!             .with_file_name("{hostname}-{pid}-{prefix}-{seq}.pprof.pb.gz")
+//!             .with_max_files(100)
+//!             .with_max_bytes(1 << 30),
+//!     );
+//!
+//!     snapshot().unwrap();
+//! }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use protobuf::Message;
+
+use crate::{
+    blocks::lock,
+    helper::{hostname, Reentrancy},
+    Profile,
+};
+
+/// The file name template used by default for gzip-compressed profiles.
+const DEFAULT_FILE_NAME: &str = "{prefix}.{timestamp}.pprof.pb.gz";
+
+/// The file name template used by default for raw profiles.
+const DEFAULT_RAW_FILE_NAME: &str = "{prefix}.{timestamp}.pprof.pb";
+
+/// The first bytes of a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Maximum length of the host name.
+const MAX_HOSTNAME: usize = 256;
+
+/// Where and how the profiles are written, see [`set_snapshot_config`].
+#[derive(Debug, Clone)]
+pub struct SnapshotConfig {
+    directory: PathBuf,
+    file_name: Option<String>,
+    max_files: Option<usize>,
+    max_bytes: Option<u64>,
+    compression: bool,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotConfig {
+    /// Create a config writing gzip-compressed profiles to the current working directory,
+    /// named `{prefix}.{timestamp}.pprof.pb.gz`, without retention.
+    pub fn new() -> Self {
+        Self {
+            directory: PathBuf::from("."),
+            file_name: None,
+            max_files: None,
+            max_bytes: None,
+            compression: true,
+        }
+    }
+
+    /// Write the profiles to `directory`, created if missing.
+    pub fn with_directory<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.directory = directory.into();
+        self
+    }
+
+    /// Name the profiles after `template`, where the placeholders are replaced by:
+    ///
+    /// - `{prefix}`: the kind of profile, `memory`, `memory.peak`, `memory.short_lived` or `memory.leaks`.
+    /// - `{pid}`: the process id.
+    /// - `{hostname}`: the host name.
+    /// - `{seq}`: the number of profiles written before by the process.
+    /// - `{timestamp}`: the local time, as `20241231T235959.123456`.
+    ///
+    /// Without `{prefix}`, `{seq}` or `{timestamp}`, each profile replaces the previous one of the same name.
+    pub fn with_file_name<S: Into<String>>(mut self, template: S) -> Self {
+        self.file_name = Some(template.into());
+        self
+    }
+
+    /// Keep at most the `max_files` newest profiles written to the directory by the process.
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+
+    /// Keep the newest profiles written to the directory by the process that fit in `max_bytes` bytes.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Whether to gzip the profiles, as expected by `pprof`, true by default.
+    ///
+    /// Applies to [`snapshot_bytes`](crate::snapshot_bytes) and [`snapshot_to_writer`](crate::snapshot_to_writer)
+    /// too. Without a file name template, raw profiles are named `{prefix}.{timestamp}.pprof.pb`.
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Returns the file name template.
+    fn file_name(&self) -> &str {
+        match &self.file_name {
+            Some(template) => template,
+            None if self.compression => DEFAULT_FILE_NAME,
+            None => DEFAULT_RAW_FILE_NAME,
+        }
+    }
+
+    /// Returns the file name of the next profile of kind `prefix`.
+    fn render(&self, prefix: &str, seq: u64) -> String {
+        let mut name = self.file_name().replace("{prefix}", prefix);
+
+        if name.contains("{pid}") {
+            name = name.replace("{pid}", &std::process::id().to_string());
+        }
+
+        if name.contains("{hostname}") {
+            let mut buf = [0u8; MAX_HOSTNAME];
+
+            let host = String::from_utf8_lossy(hostname(&mut buf)).replace(['/', '\\'], "_");
+
+            name = name.replace(
+                "{hostname}",
+                if host.is_empty() { "localhost" } else { &host },
+            );
+        }
+
+        if name.contains("{seq}") {
+            name = name.replace("{seq}", &seq.to_string());
+        }
+
+        if name.contains("{timestamp}") {
+            let datetime: DateTime<Local> = SystemTime::now().into();
+
+            name = name.replace(
+                "{timestamp}",
+                &datetime.format("%Y%m%dT%H%M%S%.6f").to_string(),
+            );
+        }
+
+        name
+    }
+
+    /// Records `written`, of `len` bytes, and removes the oldest profiles written to the
+    /// directory beyond the retention limits, `written` is always kept.
+    ///
+    /// The profiles that cannot be removed are forgotten, this may run inside the allocator
+    /// where stderr must not be locked.
+    fn retain(&self, written: &Path, len: u64) {
+        if self.max_files.is_none() && self.max_bytes.is_none() {
+            return;
+        }
+
+        // a budget crossed while the list is locked would write a snapshot and deadlock.
+        let _guard = Reentrancy::new();
+
+        let mut profiles = lock(&WRITTEN);
+
+        // a template without `{seq}` or `{timestamp}` overwrites the previous profile.
+        profiles.retain(|(path, _)| path != written);
+        profiles.push_back((written.to_path_buf(), len));
+
+        let mut files = 0;
+        let mut bytes = 0;
+        let mut exceeded = false;
+        let mut removed = vec![];
+
+        // newest first, the profile just written before all.
+        for (index, (path, len)) in profiles.iter().enumerate().rev() {
+            if path.parent() != Some(self.directory.as_path()) {
+                continue;
+            }
+
+            files += 1;
+            bytes += len;
+
+            exceeded = exceeded
+                || self.max_files.is_some_and(|max| files > max)
+                || self.max_bytes.is_some_and(|max| bytes > max);
+
+            if exceeded && path != written {
+                removed.push(index);
+            }
+        }
+
+        // highest index first, the indexes below stay valid.
+        for index in removed {
+            if let Some((path, _)) = profiles.remove(index) {
+                _ = fs::remove_file(&path);
+            }
+        }
+    }
+}
+
+static CONFIG: Mutex<Option<SnapshotConfig>> = Mutex::new(None);
+
+/// Number of profiles written by the process.
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// The profiles written by the process while a retention limit was set, oldest first,
+/// with their size.
+static WRITTEN: Mutex<VecDeque<(PathBuf, u64)>> = Mutex::new(VecDeque::new());
+
+/// Sets where and how the following profiles are written, replacing the previous config.
+///
+/// Retention applies after every profile written: the profiles written to the directory
+/// by the process while a limit was set are removed oldest first until both limits are met.
+/// The profile just written is always kept, the files of other processes are never removed.
+/// Errors removing a profile are ignored.
+pub fn set_snapshot_config(config: SnapshotConfig) {
+    // a budget crossed while the config is locked would write a snapshot and deadlock.
+    let _guard = Reentrancy::new();
+
+    let previous = lock(&CONFIG).replace(config);
+
+    drop(previous);
+}
+
+/// Returns whether the profiles are gzip-compressed.
+pub(crate) fn compression() -> bool {
+    let _guard = Reentrancy::new();
+
+    match lock(&CONFIG).as_ref() {
+        Some(config) => config.compression,
+        None => true,
+    }
+}
+
+/// Writes `profile` to `writer`, gzip-compressed if `compression` is set.
+pub(crate) fn encode_profile<W: Write>(
+    profile: &Profile,
+    mut writer: W,
+    compression: bool,
+) -> io::Result<()> {
+    if compression {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+
+        profile
+            .write_to_writer(&mut encoder)
+            .map_err(io::Error::other)?;
+
+        encoder.finish()?.flush()
+    } else {
+        profile
+            .write_to_writer(&mut writer)
+            .map_err(io::Error::other)?;
+
+        writer.flush()
+    }
+}
+
+/// Writes `profile` of kind `prefix`, returns the path of the file.
+pub(crate) fn write_profile(prefix: &str, profile: &Profile) -> io::Result<PathBuf> {
+    let config = {
+        let _guard = Reentrancy::new();
+
+        lock(&CONFIG).clone().unwrap_or_default()
+    };
+
+    let mut buf = vec![];
+
+    encode_profile(profile, &mut buf, config.compression)?;
+
+    fs::create_dir_all(&config.directory)?;
+
+    let path = config
+        .directory
+        .join(config.render(prefix, SEQ.fetch_add(1, Ordering::Relaxed)));
+
+    fs::write(&path, &buf)?;
+
+    config.retain(&path, buf.len() as u64);
+
+    Ok(path)
+}
+
+/// Decodes a profile in [`pb format`](https://github.com/google/pprof/tree/main/proto),
+/// gzip-compressed or not.
+///
+/// ```no_run
+/// use hala_pprof_memory::parse_profile;
+///
+/// let profile = parse_profile(&std::fs::read("memory.pprof.pb.gz").unwrap()).unwrap();
+///
+/// println!("{} samples", profile.sample.len());
+/// ```
+pub fn parse_profile(buf: &[u8]) -> io::Result<Profile> {
+    if buf.starts_with(&GZIP_MAGIC) {
+        let mut decoded = vec![];
+
+        GzDecoder::new(buf).read_to_end(&mut decoded)?;
+
+        return parse_raw_profile(&decoded);
+    }
+
+    parse_raw_profile(buf)
+}
+
+/// Reads a profile in [`pb format`](https://github.com/google/pprof/tree/main/proto)
+/// from `reader` to its end, gzip-compressed or not, see [`parse_profile`].
+pub fn read_profile<R: Read>(mut reader: R) -> io::Result<Profile> {
+    let mut buf = vec![];
+
+    reader.read_to_end(&mut buf)?;
+
+    parse_profile(&buf)
+}
+
+fn parse_raw_profile(buf: &[u8]) -> io::Result<Profile> {
+    Profile::parse_from_bytes(buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
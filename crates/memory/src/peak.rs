@@ -0,0 +1,153 @@
+//! High-water mark of the recorded heap.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[cfg(feature = "report")]
+use std::sync::Mutex;
+
+#[cfg(feature = "report")]
+use crate::{
+    blocks::lock,
+    metadata::{MetaAlloc, MetaVec},
+    stacks::{StackId, StackTable},
+};
+
+/// The per-stack live totals retained at the last captured peak.
+#[cfg(feature = "report")]
+pub(crate) struct PeakHeap {
+    /// Recorded live bytes at the peak.
+    pub live_bytes: usize,
+    /// Capture time, in nanoseconds since the profiler creation.
+    pub captured_at: u64,
+    /// `(stack, live blocks, live bytes)` of every stack with live blocks.
+    pub stacks: MetaVec<(StackId, usize, usize)>,
+}
+
+#[cfg(feature = "report")]
+impl Default for PeakHeap {
+    fn default() -> Self {
+        Self {
+            live_bytes: 0,
+            captured_at: 0,
+            stacks: MetaVec::new_in(MetaAlloc),
+        }
+    }
+}
+
+/// Tracks the recorded live bytes, and copies the per-stack live totals
+/// once they have grown `margin` bytes beyond the last captured peak.
+///
+/// The copy is not taken by the allocation crossing the margin, the peak is marked
+/// pending and copied by the first free that follows, the exact high-water mark,
+/// or when the peak is reported.
+///
+/// Only stack ids are retained, they stay valid until the [`StackTable`] is reset,
+/// which resets the tracker too.
+#[derive(Default)]
+pub(crate) struct PeakTracker {
+    margin: usize,
+    /// Recorded live bytes.
+    live_bytes: AtomicUsize,
+    /// The highest value of `live_bytes`.
+    peak_bytes: AtomicUsize,
+    /// The `live_bytes` of the last capture.
+    captured_bytes: AtomicUsize,
+    /// Whether `live_bytes` grew `margin` bytes beyond `captured_bytes`.
+    pending: AtomicBool,
+    #[cfg(feature = "report")]
+    peak: Mutex<PeakHeap>,
+}
+
+impl PeakTracker {
+    pub(crate) fn new(margin: usize) -> Self {
+        Self {
+            margin,
+            ..Default::default()
+        }
+    }
+
+    /// Records `size` more live bytes, marks the peak pending if they crossed the margin.
+    #[inline]
+    pub(crate) fn grow(&self, size: usize) {
+        let live = self.live_bytes.fetch_add(size, Ordering::Relaxed) + size;
+
+        if self.peak_bytes.fetch_max(live, Ordering::Relaxed) >= live {
+            return;
+        }
+
+        let captured = self.captured_bytes.load(Ordering::Relaxed);
+
+        if live > captured.saturating_add(self.margin) && !self.pending.load(Ordering::Relaxed) {
+            self.pending.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Records `size` fewer live bytes.
+    ///
+    /// Clamped to zero, a block recorded before a reset may be freed after it.
+    #[inline]
+    pub(crate) fn shrink(&self, size: usize) {
+        _ = self
+            .live_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| {
+                Some(live.saturating_sub(size))
+            });
+    }
+
+    /// Returns true if the caller must [`capture`](Self::capture) the pending peak,
+    /// before the live totals decrease. Only one caller gets true.
+    #[cfg(feature = "report")]
+    #[inline]
+    pub(crate) fn take_pending(&self) -> bool {
+        self.pending.load(Ordering::Relaxed) && self.pending.swap(false, Ordering::Relaxed)
+    }
+
+    /// Copies the live totals of `stacks` as the new peak.
+    #[cfg(feature = "report")]
+    pub(crate) fn capture(&self, stacks: &StackTable, now: u64) {
+        self.captured_bytes
+            .store(self.live_bytes.load(Ordering::Relaxed), Ordering::Relaxed);
+
+        let totals = stacks.live_totals();
+
+        let live_bytes = totals.iter().map(|(_, _, bytes)| bytes).sum();
+
+        let mut peak = lock(&self.peak);
+
+        // a concurrent capture may have copied a higher peak.
+        if live_bytes >= peak.live_bytes {
+            *peak = PeakHeap {
+                live_bytes,
+                captured_at: now,
+                stacks: totals,
+            };
+        }
+    }
+
+    /// Returns the highest recorded live bytes, exact even between captures.
+    #[cfg(feature = "report")]
+    pub(crate) fn peak_bytes(&self) -> usize {
+        self.peak_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns a copy of the last captured peak.
+    #[cfg(feature = "report")]
+    pub(crate) fn peak(&self) -> (usize, u64, Vec<(StackId, usize, usize)>) {
+        let peak = lock(&self.peak);
+
+        (peak.live_bytes, peak.captured_at, peak.stacks.to_vec())
+    }
+
+    /// Forgets the recorded bytes and the captured peak.
+    pub(crate) fn reset(&self) {
+        self.live_bytes.store(0, Ordering::Relaxed);
+        self.peak_bytes.store(0, Ordering::Relaxed);
+        self.captured_bytes.store(0, Ordering::Relaxed);
+        self.pending.store(false, Ordering::Relaxed);
+
+        #[cfg(feature = "report")]
+        {
+            *lock(&self.peak) = PeakHeap::default();
+        }
+    }
+}
@@ -1,147 +1,984 @@
 use serde::{Deserialize, Serialize};
 
-use crate::helper::{backtrace_lock, Reentrancy};
+use crate::{
+    blocks::BlockTable,
+    budget,
+    checks::{FreeChecker, MemoryErrorKind, RawMemoryError},
+    helper::{backtrace_lock, current_labels, symbolize_lock, Reentrancy},
+    labels::{LabelSetId, LabelTable},
+    metadata::{MetaBox, MetaVec},
+    peak::PeakTracker,
+    sampler::{should_sample, unsample},
+    stacks::{StackId, StackTable},
+    threads::{ThreadIndex, ThreadTable},
+    unwind::{detect_skip_frames, get_backtrace, AllocatorIps, BacktraceUnwinder, StackUnwinder},
+    usage,
+};
 
 use std::{
     alloc::{GlobalAlloc, Layout, System},
     cell::UnsafeCell,
-    collections::HashMap,
     ffi::c_void,
     mem::MaybeUninit,
+    ops::Range,
     ptr::null_mut,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+    time::{Duration, Instant},
 };
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Symbol {
     pub name: String,
     pub address: usize,
+    /// The captured instruction pointer, a runtime address unlike `address`.
+    pub ip: usize,
     pub file_name: String,
     pub line_no: u32,
     pub col_no: u32,
 }
 
-#[derive(Serialize, Deserialize)]
-pub(crate) struct Block {
-    pub size: usize,
-    pub frames: Vec<usize>,
-}
-
-/// Call this fn to get callstack, not via [`backtrace::trace`].
-///
-/// The [``backtrace``] standard api, which uses thread-local keys, may not use in GlobalAlloc.
-#[allow(unused)]
-pub(super) fn get_backtrace(max_frames: usize) -> Vec<usize> {
-    let mut stack = vec![];
-
-    let mut skips = 0;
-
-    unsafe {
-        backtrace::trace_unsynchronized(|frame| {
-            if skips < 4 {
-                skips += 1;
-                return true;
-            }
-
-            stack.push(frame.symbol_address() as usize);
+impl Symbol {
+    /// An address-only symbol, for a frame the binary has no symbol for.
+    fn unresolved(ip: usize) -> Self {
+        Self {
+            name: Default::default(),
+            address: 0,
+            ip,
+            file_name: Default::default(),
+            line_no: 0,
+            col_no: 0,
+        }
+    }
 
-            if stack.len() >= max_frames {
-                false
-            } else {
-                true
-            }
-        });
+    /// Whether the symbol only carries the address of its frame.
+    pub(crate) fn is_unresolved(&self) -> bool {
+        self.name.is_empty() && self.file_name.is_empty()
     }
+}
 
-    stack
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Block {
+    pub size: usize,
+    /// The alignment of the allocation layout.
+    pub align: usize,
+    /// The allocation stack, interned in the [`StackTable`].
+    pub stack: StackId,
+    /// Index of the allocating thread, see [`ThreadTable`].
+    pub thread: ThreadIndex,
+    /// The `(count, bytes)` estimate accounted to the allocating thread, the count
+    /// is the one of the allocation, the bytes follow the reallocations.
+    pub weight: (i64, i64),
+    /// The labels of the allocating thread, see [`LabelTable`].
+    pub labels: LabelSetId,
+    /// Allocation time, in nanoseconds since the profiler creation.
+    pub allocated_at: u64,
+    /// Whether the block is leaked on purpose, see [`mark_leaked`](crate::mark_leaked).
+    pub intentional_leak: bool,
 }
 
 /// Call this fn to convert frame symbol address to frame symbol, not via [`backtrace::resolve`]
 ///
 /// The [``backtrace``] standard api, which uses thread-local keys, may not use in GlobalAlloc.
+///
+/// Returns the symbols of every frame. The symbols of a frame are its inlined functions,
+/// innermost first, followed by the function containing the code. A frame without symbols,
+/// as in a stripped binary, has a single [unresolved](Symbol::is_unresolved) symbol.
 #[allow(unused)]
-pub(super) fn frames_to_symbols(frames: &[usize]) -> Vec<Symbol> {
+pub(super) fn frames_to_symbols(frames: &[usize]) -> Vec<Vec<Symbol>> {
     let mut symbols = vec![];
 
     for addr in frames {
-        let mut proto_symbol = None;
+        let mut frame_symbols = vec![];
 
-        // get frame symbol object.
+        // get frame symbol objects, one per inlined function.
         unsafe {
             // Safety: we provide frame to resolve symbol.
             // let _guard = backtrace_lock();
 
             backtrace::resolve_unsynchronized((*addr) as *mut c_void, |symbol| {
-                if proto_symbol.is_none() {
-                    proto_symbol = Some(Symbol {
-                        name: symbol.name().map(|s| s.to_string()).unwrap_or_default(),
-                        address: symbol.addr().unwrap_or(null_mut()) as usize,
-                        file_name: symbol
-                            .filename()
-                            .map(|path| path.to_str().unwrap().to_string())
-                            .unwrap_or_default(),
-                        line_no: symbol.lineno().unwrap_or_default(),
-                        col_no: symbol.colno().unwrap_or_default(),
-                    });
-                }
+                frame_symbols.push(Symbol {
+                    name: symbol.name().map(|s| s.to_string()).unwrap_or_default(),
+                    address: symbol.addr().unwrap_or(null_mut()) as usize,
+                    ip: *addr,
+                    file_name: symbol
+                        .filename()
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    line_no: symbol.lineno().unwrap_or_default(),
+                    col_no: symbol.colno().unwrap_or_default(),
+                });
             });
         };
 
-        if let Some(symbol) = proto_symbol {
-            symbols.push(symbol);
+        if frame_symbols.is_empty() {
+            frame_symbols.push(Symbol::unresolved(*addr));
         }
+
+        symbols.push(frame_symbols);
     }
 
     symbols
 }
 
+/// Settings of the [`HeapProfiler`], copied from [`PprofAlloc`] on first use.
+#[derive(Clone, Copy)]
+pub(crate) struct ProfilerConfig {
+    /// The maximum depth of the captured stack.
+    pub max_frames: usize,
+    /// The stack walker.
+    pub unwinder: &'static dyn StackUnwinder,
+    /// Number of innermost frames to drop, `None` detects them on first capture.
+    pub skip_frames: Option<usize>,
+    /// Average number of bytes between two sampled allocations, zero records every allocation.
+    pub sample_rate: usize,
+    /// Whether the profiler records allocations as soon as it is created.
+    pub autostart: bool,
+    /// The smallest recorded allocation size.
+    pub min_size: usize,
+    /// The largest recorded allocation size.
+    pub max_size: usize,
+    /// The recorded allocation sizes, every size if empty.
+    pub size_ranges: &'static [Range<usize>],
+    /// Growth in bytes beyond the last captured peak that triggers a new capture.
+    pub peak_margin: usize,
+    /// Whether the live blocks are reported at process exit.
+    #[cfg(feature = "report")]
+    pub leak_report: bool,
+    /// Whether deallocations are checked against the recorded blocks.
+    pub check_frees: bool,
+    /// Blocks freed less than this many nanoseconds after their allocation are short-lived.
+    pub short_lived_threshold: u64,
+}
+
+impl ProfilerConfig {
+    pub(crate) const fn new(max_frames: usize) -> Self {
+        Self {
+            max_frames,
+            unwinder: &BacktraceUnwinder,
+            skip_frames: None,
+            sample_rate: 0,
+            autostart: true,
+            min_size: 0,
+            max_size: usize::MAX,
+            size_ranges: &[],
+            peak_margin: 1024 * 1024,
+            #[cfg(feature = "report")]
+            leak_report: false,
+            check_frees: false,
+            short_lived_threshold: 100_000,
+        }
+    }
+
+    /// Returns true if every allocation is recorded while the profiler runs.
+    fn records_all(&self) -> bool {
+        self.sample_rate == 0
+            && self.min_size == 0
+            && self.max_size == usize::MAX
+            && self.size_ranges.is_empty()
+    }
+
+    /// Returns true if allocations of `size` bytes pass the size filters.
+    #[inline]
+    fn is_tracked_size(&self, size: usize) -> bool {
+        size >= self.min_size
+            && size <= self.max_size
+            && (self.size_ranges.is_empty()
+                || self.size_ranges.iter().any(|range| range.contains(&size)))
+    }
+}
+
+/// The recording state of the [`HeapProfiler`].
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProfilerState {
+    /// New allocations are not recorded, see [`stop`](crate::stop).
+    Stopped = 0,
+    /// New allocations are recorded.
+    Running = 1,
+    /// New allocations are not recorded, see [`pause`](crate::pause).
+    Paused = 2,
+}
+
+impl ProfilerState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => ProfilerState::Running,
+            2 => ProfilerState::Paused,
+            _ => ProfilerState::Stopped,
+        }
+    }
+}
+
+/// The `GlobalAlloc` method a capture happens in, each one may reach
+/// the profiler through a different number of frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AllocEntry {
+    Alloc = 0,
+    AllocZeroed = 1,
+    Dealloc = 2,
+    Realloc = 3,
+}
+
+/// What becomes of a free, decided before it reaches the inner allocator.
+pub(crate) enum Free {
+    /// The block is freed.
+    Forward,
+    /// The block is kept in quarantine, the block evicted from it, if any, is freed instead.
+    Quarantine(Option<(usize, Layout)>),
+    /// The free is invalid, nothing is freed.
+    Reject,
+}
+
+/// What becomes of a realloc, decided before it reaches the inner allocator.
+pub(crate) enum Move {
+    /// The recorded block is moved to the reallocated block.
+    Recorded(Block),
+    /// The block is not recorded, `hidden` if the profiler allocated it itself.
+    Unrecorded { hidden: bool },
+    /// The realloc is invalid, nothing is reallocated.
+    Reject,
+}
+
+/// Number of frames captured to detect the skip count.
+const CALIBRATION_FRAMES: usize = 64;
+
+/// Marks a skip count not detected yet.
+const UNCALIBRATED: usize = usize::MAX;
+
+/// Most allocator frames between the profiler entry point and the caller,
+/// the `GlobalAlloc` implementation and the rustc shims.
+const MAX_ALLOCATOR_FRAMES: usize = 4;
+
 pub(crate) struct HeapProfiler {
-    max_frames: usize,
-    blocks: UnsafeCell<HashMap<usize, Block>>,
+    config: ProfilerConfig,
+    /// The origin of block timestamps.
+    epoch: Instant,
+    /// The detected skip count of each [`AllocEntry`], up to the profiler entry point.
+    skip_frames: [AtomicUsize; 4],
+    /// Whether the frame at an address is an allocator frame.
+    allocator_ips: AllocatorIps,
+    state: AtomicU8,
+    /// Whether every live allocation is recorded: the profiler records all allocations,
+    /// and has been running since its creation, without reset.
+    complete: AtomicBool,
+    /// Number of recorded blocks that are still live.
+    live_blocks: AtomicUsize,
+    /// The high-water mark of the recorded blocks.
+    peak: PeakTracker,
+    /// Number of allocations rejected by the size filters.
+    untracked_count: AtomicUsize,
+    /// Total bytes of the allocations rejected by the size filters.
+    untracked_bytes: AtomicUsize,
+    blocks: BlockTable,
+    /// Interned allocation stacks, with their cumulative statistics.
+    stacks: StackTable,
+    /// Accounting of the allocating threads.
+    threads: ThreadTable,
+    /// User-defined label sets.
+    labels: LabelTable,
+    /// The freed blocks and detected errors, when checking frees.
+    checker: FreeChecker,
 }
 
 impl HeapProfiler {
     /// Create a  new `HeapProfiler` instance.
-    fn new(max_frames: usize) -> Option<Self> {
+    fn new(config: ProfilerConfig) -> Option<Self> {
+        let state = if config.autostart {
+            ProfilerState::Running
+        } else {
+            ProfilerState::Stopped
+        };
+
         Some(Self {
-            max_frames,
+            config,
+            epoch: Instant::now(),
+            skip_frames: std::array::from_fn(|_| AtomicUsize::new(UNCALIBRATED)),
+            allocator_ips: Default::default(),
+            state: AtomicU8::new(state as u8),
+            complete: AtomicBool::new(config.autostart && config.records_all()),
+            live_blocks: AtomicUsize::new(0),
+            peak: PeakTracker::new(config.peak_margin),
+            untracked_count: AtomicUsize::new(0),
+            untracked_bytes: AtomicUsize::new(0),
             blocks: Default::default(),
+            stacks: Default::default(),
+            threads: Default::default(),
+            labels: Default::default(),
+            checker: Default::default(),
         })
     }
 
-    fn register(&self, ptr: *mut u8, layout: Layout) {
-        let _locker = backtrace_lock();
+    /// Returns true if new allocations are recorded.
+    #[inline]
+    fn is_running(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == ProfilerState::Running as u8
+    }
+
+    /// Returns true if frees must be looked up in the block table.
+    #[inline]
+    fn has_live_blocks(&self) -> bool {
+        self.live_blocks.load(Ordering::Relaxed) > 0
+    }
+
+    pub(crate) fn state(&self) -> ProfilerState {
+        ProfilerState::from_u8(self.state.load(Ordering::Acquire))
+    }
+
+    pub(crate) fn set_state(&self, state: ProfilerState) {
+        if state != ProfilerState::Running {
+            self.complete.store(false, Ordering::Relaxed);
+        }
+
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    /// Atomically changes the state from `current` to `new`, returns false if the state was not `current`.
+    pub(crate) fn transit(&self, current: ProfilerState, new: ProfilerState) -> bool {
+        let transited = self
+            .state
+            .compare_exchange(
+                current as u8,
+                new as u8,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok();
+
+        if transited && new != ProfilerState::Running {
+            self.complete.store(false, Ordering::Relaxed);
+        }
+
+        transited
+    }
+
+    /// Returns true if allocations of `size` bytes are recorded, otherwise
+    /// only adds them to the untracked totals, without capturing their stack.
+    #[inline]
+    fn track(&self, size: usize) -> bool {
+        if self.config.is_tracked_size(size) {
+            return true;
+        }
+
+        self.untracked_count.fetch_add(1, Ordering::Relaxed);
+        self.untracked_bytes.fetch_add(size, Ordering::Relaxed);
+
+        false
+    }
+
+    /// Returns the monotonic time since the profiler creation, in nanoseconds.
+    #[inline]
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_nanos() as u64
+    }
+
+    pub(crate) fn labels(&self) -> &LabelTable {
+        &self.labels
+    }
+
+    pub(crate) fn threads(&self) -> &ThreadTable {
+        &self.threads
+    }
+
+    pub(crate) fn checker(&self) -> &FreeChecker {
+        &self.checker
+    }
+
+    /// Returns true if frees must be looked up even without live blocks.
+    #[inline]
+    fn checks_frees(&self) -> bool {
+        self.config.check_frees
+    }
+
+    /// Drops all recorded blocks and cumulative statistics.
+    pub(crate) fn reset(&self) {
+        let mut blocks = self.blocks.lock_all();
+
+        for (_, block) in blocks.iter() {
+            self.stacks.free(block.stack, block.size);
+        }
+
+        blocks.clear();
+
+        self.stacks.reset();
+
+        // the peak refers to stacks the reset may have dropped.
+        self.peak.reset();
+
+        self.threads.reset();
+
+        // the live allocations are no longer all recorded.
+        self.complete.store(false, Ordering::Relaxed);
+
+        self.live_blocks.store(0, Ordering::Relaxed);
+
+        self.untracked_count.store(0, Ordering::Relaxed);
+        self.untracked_bytes.store(0, Ordering::Relaxed);
+    }
+
+    /// Captures the current call stack, without the profiler and allocator frames.
+    ///
+    /// Only the walks of an unwinder that [needs it](StackUnwinder::needs_lock) are serialized,
+    /// the frames are symbolized outside of the walks.
+    #[inline(never)]
+    fn capture(&self, entry: AllocEntry) -> MetaVec<usize> {
+        let unwinder = self.config.unwinder;
+
+        let skip = match self.config.skip_frames {
+            Some(skip) => {
+                let _locker = unwinder.needs_lock().then(backtrace_lock);
+
+                return get_backtrace(unwinder, skip, self.config.max_frames);
+            }
+            None => {
+                let detected = &self.skip_frames[entry as usize];
+
+                let mut skip = detected.load(Ordering::Relaxed);
+
+                if skip == UNCALIBRATED {
+                    // the calibration trace must be taken at the same depth as the capture below.
+                    let frames = {
+                        let _locker = unwinder.needs_lock().then(backtrace_lock);
+
+                        get_backtrace(unwinder, 0, CALIBRATION_FRAMES)
+                    };
+
+                    let _symbolizer = symbolize_lock();
+
+                    skip = detect_skip_frames(&frames).unwrap_or(0);
+
+                    detected.store(skip, Ordering::Relaxed);
+                }
+
+                skip
+            }
+        };
+
+        let mut frames = {
+            let _locker = unwinder.needs_lock().then(backtrace_lock);
+
+            get_backtrace(
+                unwinder,
+                skip,
+                self.config.max_frames + MAX_ALLOCATOR_FRAMES,
+            )
+        };
+
+        let allocator = self.allocator_frames(&frames);
+
+        frames.drain(..allocator);
+        frames.truncate(self.config.max_frames);
+
+        frames
+    }
+
+    /// Returns the number of innermost `frames` that are allocator frames,
+    /// whether they are inlined into the caller depends on the call site.
+    fn allocator_frames(&self, frames: &[usize]) -> usize {
+        frames
+            .iter()
+            .take(MAX_ALLOCATOR_FRAMES)
+            .take_while(|ip| self.allocator_ips.is_allocator(**ip))
+            .count()
+    }
+
+    fn register(&self, ptr: *mut u8, layout: Layout, entry: AllocEntry) {
+        let frames = self.capture(entry);
 
-        let frames = get_backtrace(self.max_frames);
+        if self.config.check_frees {
+            // the address is reused, a free is no longer a double free.
+            self.checker.forget(ptr as usize);
+        }
+
+        let weight = self.estimate(layout.size());
 
         let block = Block {
             size: layout.size(),
-            frames,
+            align: layout.align(),
+            stack: self.stacks.alloc(&frames, layout.size()),
+            thread: self.threads.alloc(weight.0, weight.1),
+            weight,
+            labels: current_labels(),
+            allocated_at: self.now(),
+            intentional_leak: false,
         };
 
-        unsafe { &mut *self.blocks.get() }.insert(ptr as usize, block);
+        match self.blocks.insert(ptr as usize, block) {
+            // the previous block was freed while the profiler could not see it.
+            Some(prev) => self.drop_stale(prev),
+            None => {
+                self.live_blocks.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.grow(layout.size());
+    }
+
+    /// Unregisters the block at `ptr`, before it is returned to the inner allocator.
+    fn unregister(&self, ptr: *mut u8, layout: Layout) -> Free {
+        let Some(block) = self.blocks.remove(ptr as usize) else {
+            // freed after an allocation inside the profiler.
+            if self.config.check_frees && !self.checker.unhide(ptr as usize) {
+                let frames = self.capture(AllocEntry::Dealloc);
+
+                let reported = self.checker.invalid_free(
+                    ptr as usize,
+                    layout,
+                    &frames,
+                    self.complete.load(Ordering::Relaxed),
+                );
+
+                if reported {
+                    return Free::Reject;
+                }
+            }
+
+            return Free::Forward;
+        };
+
+        self.live_blocks.fetch_sub(1, Ordering::Relaxed);
+
+        let mut free = Free::Forward;
+
+        if self.config.check_frees {
+            // captured here, at the same depth as above, they share the detected skip count.
+            let frames = self.capture(AllocEntry::Dealloc);
+
+            free = Free::Quarantine(self.check_free(ptr, layout, &block, &frames));
+        }
+
+        let lifetime = self.now().saturating_sub(block.allocated_at);
+
+        self.capture_peak();
+
+        self.stacks.free_after(
+            block.stack,
+            block.size,
+            lifetime,
+            lifetime < self.config.short_lived_threshold,
+        );
+
+        self.threads
+            .free(block.thread, block.weight.0, block.weight.1);
+
+        self.peak.shrink(block.size);
+
+        free
+    }
+
+    /// Returns the `(count, bytes)` estimate of the unsampled allocations
+    /// represented by a recorded block of `size` bytes.
+    #[inline]
+    fn estimate(&self, size: usize) -> (i64, i64) {
+        unsample(self.config.sample_rate, 1, size)
+    }
+
+    /// Notes the allocation at `ptr` that is not recorded, when checking frees.
+    ///
+    /// `reentrant` allocations are made by the profiler itself, their free is not an error.
+    #[inline]
+    fn skip(&self, ptr: *mut u8, reentrant: bool) {
+        if !self.config.check_frees {
+            return;
+        }
+
+        // the address is reused, a free is no longer a double free.
+        self.checker.forget(ptr as usize);
+
+        if reentrant {
+            self.checker.hide(ptr as usize);
+        }
+    }
+
+    /// Forgets the allocation at `ptr` that is not recorded, before it is freed or
+    /// reallocated, returns true if the profiler allocated it itself.
+    #[inline]
+    fn skip_free(&self, ptr: *mut u8) -> bool {
+        self.config.check_frees && self.checker.unhide(ptr as usize)
+    }
+
+    /// Checks the free of the recorded `block` with `layout`, and quarantines it.
+    ///
+    /// Returns the block evicted from the quarantine, which must be freed now.
+    fn check_free(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        block: &Block,
+        free_frames: &[usize],
+    ) -> Option<(usize, Layout)> {
+        let alloc_frames = self.stacks.frames(block.stack);
+
+        let recorded = unsafe { Layout::from_size_align_unchecked(block.size, block.align) };
+
+        let mismatch = recorded != layout;
+
+        if mismatch {
+            self.checker.report(RawMemoryError {
+                kind: MemoryErrorKind::LayoutMismatch,
+                address: ptr as usize,
+                layout,
+                recorded_layout: Some(recorded),
+                alloc_frames: MetaBox::from(&*alloc_frames),
+                free_frames: MetaBox::from(free_frames),
+                previous_free_frames: MetaBox::from(&[][..]),
+            });
+        }
+
+        // a mismatched block is never returned, it stays in quarantine to catch its next free.
+        self.checker.quarantine(
+            ptr as usize,
+            recorded,
+            !mismatch,
+            &alloc_frames,
+            free_frames,
+        )
+    }
+
+    /// Excludes the block at `ptr` from the leak reports, returns false if it is not recorded.
+    pub(crate) fn mark_leaked(&self, ptr: *const u8) -> bool {
+        self.blocks
+            .update(ptr as usize, |block| block.intentional_leak = true)
+            .is_some()
+    }
+
+    /// Adds `size` recorded live bytes.
+    fn grow(&self, size: usize) {
+        self.peak.grow(size);
+    }
+
+    /// Captures the pending peak, called before the live totals decrease.
+    fn capture_peak(&self) {
+        // the copy is only read by the peak report.
+        #[cfg(feature = "report")]
+        if self.peak.take_pending() {
+            self.peak.capture(&self.stacks, self.now());
+        }
+    }
+
+    /// Removes the block recorded at `ptr` before it is reallocated for `layout`,
+    /// see [`attach`](Self::attach).
+    ///
+    /// The inner allocator may free `ptr` and hand it out to another thread before
+    /// the realloc returns, the block must not be found at `ptr` by then. When checking
+    /// frees, the realloc of a freed or unknown pointer, or with a wrong layout, is rejected.
+    fn detach(&self, ptr: *mut u8, layout: Layout) -> Move {
+        let Some(block) = self.blocks.remove(ptr as usize) else {
+            if !self.config.check_frees {
+                return Move::Unrecorded { hidden: false };
+            }
+
+            if self.checker.unhide(ptr as usize) {
+                return Move::Unrecorded { hidden: true };
+            }
+
+            let frames = self.capture(AllocEntry::Realloc);
+
+            let reported = self.checker.invalid_free(
+                ptr as usize,
+                layout,
+                &frames,
+                self.complete.load(Ordering::Relaxed),
+            );
+
+            if reported {
+                return Move::Reject;
+            }
+
+            return Move::Unrecorded { hidden: false };
+        };
+
+        let recorded = unsafe { Layout::from_size_align_unchecked(block.size, block.align) };
+
+        if self.config.check_frees && recorded != layout {
+            let frames = self.capture(AllocEntry::Realloc);
+
+            self.checker.report(RawMemoryError {
+                kind: MemoryErrorKind::LayoutMismatch,
+                address: ptr as usize,
+                layout,
+                recorded_layout: Some(recorded),
+                alloc_frames: self.stacks.frames(block.stack),
+                free_frames: MetaBox::from(&*frames),
+                previous_free_frames: MetaBox::from(&[][..]),
+            });
+
+            // the block stays live, the caller still owns it.
+            self.blocks.insert(ptr as usize, block);
+
+            return Move::Reject;
+        }
+
+        Move::Recorded(block)
+    }
+
+    /// Records the `block` detached from `ptr` at `new_ptr`, resized to `new_size`,
+    /// or back at `ptr` if `new_ptr` is null, the realloc failed.
+    ///
+    /// The allocation stack of the block is kept.
+    fn attach(&self, ptr: *mut u8, new_ptr: *mut u8, mut block: Block, new_size: usize) {
+        if new_ptr.is_null() {
+            self.blocks.insert(ptr as usize, block);
+            return;
+        }
+
+        let old_size = block.size;
+
+        if new_size < old_size {
+            self.capture_peak();
+        }
+
+        self.stacks.realloc(block.stack, old_size, new_size);
+
+        let bytes = self.estimate(new_size).1;
+
+        self.threads.realloc(block.thread, block.weight.1, bytes);
+
+        block.size = new_size;
+        block.weight.1 = bytes;
+
+        if self.config.check_frees {
+            self.checker.forget(new_ptr as usize);
+        }
+
+        if let Some(prev) = self.blocks.insert(new_ptr as usize, block) {
+            // the previous block was freed while the profiler could not see it.
+            self.live_blocks.fetch_sub(1, Ordering::Relaxed);
+            self.drop_stale(prev);
+        }
+
+        if new_size >= old_size {
+            self.grow(new_size - old_size);
+        } else {
+            self.peak.shrink(old_size - new_size);
+        }
+    }
+
+    /// Drops the statistics of the block replaced in the table by a new one at its address.
+    fn drop_stale(&self, block: Block) {
+        self.capture_peak();
+        self.stacks.free(block.stack, block.size);
+        self.threads
+            .free(block.thread, block.weight.0, block.weight.1);
+        self.peak.shrink(block.size);
     }
 
-    fn unregister(&self, ptr: *mut u8, _layout: Layout) {
-        let _locker = backtrace_lock();
+    #[cfg(feature = "report")]
+    pub fn report(&self, mode: crate::ReportMode) -> crate::proto::gperf::Profile {
+        use crate::report::{age_bucket, GperfHeapProfilerReport, LabelValue};
+        use crate::ReportMode;
+        use std::collections::HashMap;
+
+        let now = self.now();
+
+        // copy the tables out first, symbolization is slow and must not block allocations,
+        // it only takes the symbolizer lock, not the one of the stack captures.
+        let blocks = self
+            .blocks
+            .lock_all()
+            .iter()
+            .map(|(ptr, block)| (*ptr, block.clone()))
+            .collect::<Vec<_>>();
+
+        let stacks = self.stacks.entries();
+
+        let threads = self.threads.names().into_iter().collect::<HashMap<_, _>>();
+
+        let _symbolizer = symbolize_lock();
+
+        // every stack is symbolized once, however many blocks share it.
+        let symbols = stacks
+            .iter()
+            .map(|(id, frames, _)| (*id, frames_to_symbols(frames)))
+            .collect::<HashMap<_, _>>();
+
+        let mut label_sets = HashMap::new();
+
+        let mut reporter = GperfHeapProfilerReport::new(self.config.sample_rate);
+
+        reporter.report_comment(&format!(
+            "profiler overhead: {} bytes",
+            crate::profiler_overhead()
+        ));
+
+        if self.config.leak_report && crate::leaks::leak_report_unregistered() {
+            reporter.report_comment("leak report: failed to register the exit hook");
+        }
+
+        match mode {
+            ReportMode::Blocks | ReportMode::Leaks => {
+                let mut leaks = (0, 0);
+
+                for (ptr, block) in blocks {
+                    if mode == ReportMode::Leaks {
+                        if block.intentional_leak {
+                            continue;
+                        }
+
+                        leaks.0 += 1;
+                        leaks.1 += block.size;
+                    }
+
+                    if let Some(symbols) = symbols.get(&block.stack) {
+                        let thread = threads
+                            .get(&block.thread)
+                            .map(|(id, name)| (*id, name.as_str()))
+                            .unwrap_or_default();
+
+                        let labels = label_sets
+                            .entry(block.labels)
+                            .or_insert_with(|| self.labels.get(block.labels));
+
+                        let age = Duration::from_nanos(now.saturating_sub(block.allocated_at));
+
+                        reporter.report_block_info(
+                            ptr as *mut u8,
+                            &block,
+                            age,
+                            thread,
+                            labels,
+                            symbols,
+                        );
+                    }
+                }
+
+                if mode == ReportMode::Leaks {
+                    reporter
+                        .report_comment(&format!("leaks: {} blocks, {} bytes", leaks.0, leaks.1));
+                }
+            }
+            ReportMode::Threads => {
+                // live (count, bytes) per stack and allocating thread.
+                let mut groups = HashMap::<_, (usize, usize)>::new();
+
+                for (_, block) in blocks {
+                    let group = groups.entry((block.stack, block.thread)).or_default();
+
+                    group.0 += 1;
+                    group.1 += block.size;
+                }
+
+                for ((stack, thread), (count, bytes)) in groups {
+                    if let Some(symbols) = symbols.get(&stack) {
+                        let (thread_id, thread_name) = threads
+                            .get(&thread)
+                            .map(|(id, name)| (*id, name.as_str()))
+                            .unwrap_or_default();
+
+                        reporter.report_group_info(
+                            &[
+                                ("thread_id", LabelValue::Num(thread_id as i64)),
+                                ("thread_name", LabelValue::Str(thread_name)),
+                            ],
+                            count,
+                            bytes,
+                            symbols,
+                        );
+                    }
+                }
+            }
+            ReportMode::AgeBuckets => {
+                // live (count, bytes) per stack and age bucket.
+                let mut groups = HashMap::<_, (usize, usize)>::new();
 
-        unsafe { &mut *self.blocks.get() }.remove(&(ptr as usize));
+                for (_, block) in blocks {
+                    let age = Duration::from_nanos(now.saturating_sub(block.allocated_at));
+
+                    let group = groups.entry((block.stack, age_bucket(age))).or_default();
+
+                    group.0 += 1;
+                    group.1 += block.size;
+                }
+
+                for ((stack, bucket), (count, bytes)) in groups {
+                    if let Some(symbols) = symbols.get(&stack) {
+                        reporter.report_group_info(
+                            &[("age_bucket", LabelValue::Str(bucket))],
+                            count,
+                            bytes,
+                            symbols,
+                        );
+                    }
+                }
+            }
+        }
+
+        for (id, _, stats) in stacks {
+            if stats.count > 0 {
+                reporter.report_alloc_info(&stats, &symbols[&id]);
+            }
+        }
+
+        let untracked_count = self.untracked_count.load(Ordering::Relaxed);
+
+        if untracked_count > 0 {
+            reporter.report_untracked(
+                untracked_count,
+                self.untracked_bytes.load(Ordering::Relaxed),
+            );
+        }
+
+        reporter.build()
     }
 
+    /// Returns the profile of the lifetimes of the freed blocks, per stack.
     #[cfg(feature = "report")]
-    pub fn report(&self) -> crate::proto::gperf::Profile {
-        let _locker = backtrace_lock();
+    pub fn report_lifetimes(&self) -> crate::proto::gperf::Profile {
+        use crate::report::GperfHeapProfilerReport;
+
+        let stacks = self.stacks.entries();
+
+        let _symbolizer = symbolize_lock();
+
+        let mut reporter = GperfHeapProfilerReport::lifetimes(self.config.sample_rate);
+
+        reporter.report_comment(&format!(
+            "short-lived threshold: {}us",
+            self.config.short_lived_threshold / 1000
+        ));
+
+        for (_, frames, stats) in stacks {
+            if stats.lifetimes.iter().any(|freed| *freed > 0) {
+                reporter.report_lifetime_info(&stats, &frames_to_symbols(&frames));
+            }
+        }
 
+        reporter.build()
+    }
+
+    /// Returns the profile of the live blocks at the last captured peak,
+    /// only the `inuse_objects`/`inuse_space` values are filled.
+    #[cfg(feature = "report")]
+    pub fn report_peak(&self) -> crate::proto::gperf::Profile {
         use crate::report::GperfHeapProfilerReport;
+        use std::collections::HashMap;
 
-        let mut reporter = GperfHeapProfilerReport::new();
+        // nothing was freed since the pending peak, the live totals are the peak.
+        self.capture_peak();
 
-        for (ptr, block) in unsafe { &mut *self.blocks.get() }.iter() {
-            reporter.report_block_info(
-                (*ptr) as *mut u8,
-                block.size,
-                &frames_to_symbols(&block.frames),
-            );
+        let (live_bytes, captured_at, totals) = self.peak.peak();
+
+        let stacks = self.stacks.entries();
+
+        let _symbolizer = symbolize_lock();
+
+        let symbols = stacks
+            .iter()
+            .map(|(id, frames, _)| (*id, frames_to_symbols(frames)))
+            .collect::<HashMap<_, _>>();
+
+        let mut reporter = GperfHeapProfilerReport::new(self.config.sample_rate);
+
+        reporter.report_comment(&format!(
+            "peak: {} bytes captured {:.3}s after start, highest {} bytes",
+            live_bytes,
+            Duration::from_nanos(captured_at).as_secs_f64(),
+            self.peak.peak_bytes(),
+        ));
+
+        for (stack, count, bytes) in totals {
+            if let Some(symbols) = symbols.get(&stack) {
+                reporter.report_group_info(&[], count, bytes, symbols);
+            }
         }
 
         reporter.build()
@@ -161,14 +998,14 @@ impl GLobalHeapProfiler {
         }
     }
 
-    fn get(&self, max_frames: usize) -> Option<&HeapProfiler> {
+    fn get(&self, config: &ProfilerConfig) -> Option<&HeapProfiler> {
         while self.initialized.load(Ordering::Acquire) < 2 {
             if self
                 .initialized
                 .compare_exchange(0, 1, Ordering::AcqRel, Ordering::Relaxed)
                 .is_ok()
             {
-                let profiler = match HeapProfiler::new(max_frames) {
+                let profiler = match HeapProfiler::new(*config) {
                     Some(profiler) => profiler,
                     None => {
                         assert!(self
@@ -182,6 +1019,14 @@ impl GLobalHeapProfiler {
                 unsafe { (&mut *self.profiler.get()).write(profiler) };
 
                 self.initialized.fetch_add(1, Ordering::Release);
+
+                #[cfg(feature = "report")]
+                if config.leak_report {
+                    // registering the hook may allocate.
+                    let _guard = Reentrancy::new();
+
+                    crate::leaks::register_leak_report();
+                }
             }
         }
 
@@ -196,49 +1041,380 @@ impl GLobalHeapProfiler {
 unsafe impl Sync for GLobalHeapProfiler {}
 unsafe impl Send for GLobalHeapProfiler {}
 
-pub(crate) fn global_heap_profiler(max_frames: usize) -> Option<&'static HeapProfiler> {
-    static PROFILER: GLobalHeapProfiler = GLobalHeapProfiler::new();
+static PROFILER: GLobalHeapProfiler = GLobalHeapProfiler::new();
 
-    PROFILER.get(max_frames)
+pub(crate) fn global_heap_profiler(config: &ProfilerConfig) -> Option<&'static HeapProfiler> {
+    PROFILER.get(config)
+}
+
+/// Returns the global profiler if a [`PprofAlloc`] has already created it.
+pub(crate) fn initialized_heap_profiler() -> Option<&'static HeapProfiler> {
+    if PROFILER.initialized.load(Ordering::Acquire) == 2 {
+        Some(unsafe { (&*PROFILER.profiler.get()).assume_init_ref() })
+    } else {
+        None
+    }
 }
 
 /// An implementation of [`GlobalAlloc`] that supports memory profiling.
 ///
 /// If you are very concerned about memory usage, you can reduce it by
 /// appropriately decreasing the maximum depth of the capture stack.
-pub struct PprofAlloc(
-    /// The maximum depth of the captured stack.
-    pub usize,
-);
+///
+/// By default every allocation is recorded, which requires a full stack capture
+/// per allocation. Long-running services should enable sampling instead:
+///
+/// ```no_run
+/// use hala_pprof_memory::PprofAlloc;
+///
+/// // record on average one allocation per 512 KiB allocated.
+/// #[global_allocator]
+/// static ALLOC: PprofAlloc = PprofAlloc::new(10).with_sample_rate(512 * 1024);
+/// ```
+///
+/// The memory itself is provided by the inner allocator `A`, [`System`] by default,
+/// any other [`GlobalAlloc`] can be profiled with [`PprofAlloc::from_allocator`].
+pub struct PprofAlloc<A: GlobalAlloc = System> {
+    inner: A,
+    config: ProfilerConfig,
+}
 
-unsafe impl GlobalAlloc for PprofAlloc {
-    #[inline]
-    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
-        let ptr = System.alloc(layout);
+impl PprofAlloc<System> {
+    /// Create a new allocator that captures at most `max_frames` stack frames per allocation.
+    pub const fn new(max_frames: usize) -> Self {
+        Self::from_allocator(System, max_frames)
+    }
+}
+
+impl<A: GlobalAlloc> PprofAlloc<A> {
+    /// Create a new allocator that profiles the allocations served by `allocator`,
+    /// capturing at most `max_frames` stack frames per allocation.
+    ///
+    /// ```no_run
+    /// use std::alloc::System;
+    /// use hala_pprof_memory::PprofAlloc;
+    ///
+    /// // any `GlobalAlloc` implementation, e.g. `jemallocator::Jemalloc`.
+    /// #[global_allocator]
+    /// static ALLOC: PprofAlloc<System> = PprofAlloc::from_allocator(System, 10);
+    /// ```
+    pub const fn from_allocator(allocator: A, max_frames: usize) -> Self {
+        Self {
+            inner: allocator,
+            config: ProfilerConfig::new(max_frames),
+        }
+    }
+
+    /// Record on average one allocation per `sample_rate` bytes allocated.
+    ///
+    /// Sampled allocations are chosen by a poisson process, as tcmalloc and go do,
+    /// and reports scale sample values back to estimates of the unsampled heap.
+    /// Zero, the default, records every allocation.
+    pub const fn with_sample_rate(mut self, sample_rate: usize) -> Self {
+        self.config.sample_rate = sample_rate;
+        self
+    }
+
+    /// Walk the call stacks with `unwinder`, [`BacktraceUnwinder`] by default.
+    ///
+    /// ```no_run
+    /// use hala_pprof_memory::{FramePointerUnwinder, PprofAlloc};
+    ///
+    /// // requires building with `-C force-frame-pointers=yes`.
+    /// #[global_allocator]
+    /// static ALLOC: PprofAlloc = PprofAlloc::new(10).with_unwinder(&FramePointerUnwinder);
+    /// ```
+    pub const fn with_unwinder(mut self, unwinder: &'static dyn StackUnwinder) -> Self {
+        self.config.unwinder = unwinder;
+        self
+    }
+
+    /// Drop the `skip_frames` innermost frames of every captured stack.
+    ///
+    /// By default the frames of the profiler and of the allocator shims are detected
+    /// by symbolizing the first captured stack, which requires debug symbols.
+    pub const fn with_skip_frames(mut self, skip_frames: usize) -> Self {
+        self.config.skip_frames = Some(skip_frames);
+        self
+    }
+
+    /// Only record the allocations of `min_size` to `max_size` bytes, both inclusive.
+    ///
+    /// The other allocations skip the stack capture, they are only counted in
+    /// aggregate and reported as the cumulative values of a synthetic `[untracked]` sample.
+    ///
+    /// ```no_run
+    /// use hala_pprof_memory::PprofAlloc;
+    ///
+    /// // only allocations of 4 KiB or more are worth a backtrace.
+    /// #[global_allocator]
+    /// static ALLOC: PprofAlloc = PprofAlloc::new(10).with_size_range(4096, usize::MAX);
+    /// ```
+    pub const fn with_size_range(mut self, min_size: usize, max_size: usize) -> Self {
+        self.config.min_size = min_size;
+        self.config.max_size = max_size;
+        self
+    }
+
+    /// Only record the allocations whose size is in one of `ranges`,
+    /// combined with [`with_size_range`](Self::with_size_range) if both are set.
+    ///
+    /// ```no_run
+    /// use hala_pprof_memory::PprofAlloc;
+    ///
+    /// #[global_allocator]
+    /// static ALLOC: PprofAlloc = PprofAlloc::new(10).with_size_ranges(&[48..64, 65536..usize::MAX]);
+    /// ```
+    pub const fn with_size_ranges(mut self, ranges: &'static [Range<usize>]) -> Self {
+        self.config.size_ranges = ranges;
+        self
+    }
+
+    /// Capture the per-stack live totals once the recorded live bytes grew
+    /// `peak_margin` bytes beyond the last capture, 1 MiB by default.
+    ///
+    /// The totals are copied by the first free that follows, at the high-water mark,
+    /// not by the allocation crossing the margin. The last capture is the profile
+    /// written by [`peak_snapshot`](crate::peak_snapshot). A smaller margin catches
+    /// smaller peaks, at the cost of more copies.
+    pub const fn with_peak_margin(mut self, peak_margin: usize) -> Self {
+        self.config.peak_margin = peak_margin;
+        self
+    }
+
+    /// Write a profile of the blocks still live at process exit, see [`mark_leaked`](crate::mark_leaked)
+    /// to exclude the blocks leaked on purpose.
+    ///
+    /// If the exit hook cannot be registered, the profiles written by the process say so in a comment.
+    ///
+    /// ```no_run
+    /// use hala_pprof_memory::PprofAlloc;
+    ///
+    /// // writes `./memory.leaks.<timestamp>.pprof.pb.gz` when the tests exit.
+    /// #[global_allocator]
+    /// static ALLOC: PprofAlloc = PprofAlloc::new(10).with_leak_report(true);
+    /// ```
+    #[cfg(feature = "report")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "report")))]
+    pub const fn with_leak_report(mut self, leak_report: bool) -> Self {
+        self.config.leak_report = leak_report;
+        self
+    }
+
+    /// Check every deallocation against the recorded blocks, collecting double frees,
+    /// frees of unknown pointers and layout mismatches with their allocation and free
+    /// stacks, see [`memory_errors`](crate::memory_errors).
+    ///
+    /// Each checked free captures a stack, this mode is meant for tests.
+    /// Frees of unknown pointers are only reported while every live allocation is
+    /// recorded: without sampling nor size filters, and with the profiler running
+    /// since its creation.
+    ///
+    /// Frees and reallocs are checked before they reach the inner allocator, the reported
+    /// ones are not forwarded: their blocks are leaked rather than freed twice or with a wrong
+    /// layout, and a rejected realloc returns null.
+    /// The last 4096 freed blocks are kept allocated in quarantine, so that their addresses
+    /// are not reused while a second free of them is still detected.
+    pub const fn with_free_checks(mut self, check_frees: bool) -> Self {
+        self.config.check_frees = check_frees;
+        self
+    }
+
+    /// Count the blocks freed less than `threshold` after their allocation as short-lived,
+    /// 100us by default, see [`short_lived_snapshot`](crate::short_lived_snapshot).
+    pub const fn with_short_lived_threshold(mut self, threshold: Duration) -> Self {
+        self.config.short_lived_threshold = threshold.as_nanos() as u64;
+        self
+    }
+
+    /// Whether to record allocations from the start of the program, the default.
+    ///
+    /// With `false`, nothing is recorded until [`start`](crate::start) is called.
+    pub const fn with_autostart(mut self, autostart: bool) -> Self {
+        self.config.autostart = autostart;
+        self
+    }
+}
+
+impl<A: GlobalAlloc> PprofAlloc<A> {
+    /// Records the block at `ptr` just returned by the inner allocator for `layout`, returns `ptr`.
+    ///
+    /// Never inlined, this is where the skipped frames of a captured stack end.
+    #[inline(never)]
+    unsafe fn on_alloc(&self, ptr: *mut u8, layout: Layout, entry: AllocEntry) -> *mut u8 {
+        if ptr.is_null() {
+            return ptr;
+        }
 
+        let live = usage::on_alloc(layout.size());
+
+        // taken first, the profiler creation and the budget callbacks may allocate.
         let guard = Reentrancy::new();
 
         if !guard.is_ok() {
+            if let Some(profiler) = initialized_heap_profiler() {
+                profiler.skip(ptr, true);
+            }
+
             return ptr;
         }
 
-        if let Some(profiler) = global_heap_profiler(self.0) {
-            profiler.register(ptr, layout);
+        budget::on_grow(live);
+
+        let Some(profiler) = global_heap_profiler(&self.config) else {
+            return ptr;
+        };
+
+        if !profiler.is_running() || !profiler.track(layout.size()) {
+            profiler.skip(ptr, false);
+            return ptr;
+        }
+
+        if should_sample(self.config.sample_rate, layout.size()) {
+            profiler.register(ptr, layout, entry);
+        } else {
+            profiler.skip(ptr, false);
         }
 
         ptr
     }
 
-    #[inline]
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
-        System.dealloc(ptr, layout);
+    /// Unregisters the block at `ptr` freed for `layout`, then frees it with the inner
+    /// allocator, unless the free checks keep it in quarantine or reject the free.
+    ///
+    /// The block is unregistered first, the inner allocator cannot hand out its address
+    /// again before. Never inlined, this is where the skipped frames of a captured stack end.
+    #[inline(never)]
+    unsafe fn on_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let guard = Reentrancy::new();
+
+        if !guard.is_ok() {
+            usage::on_free(layout.size());
+
+            if let Some(profiler) = initialized_heap_profiler() {
+                profiler.skip_free(ptr);
+            }
 
+            return self.inner.dealloc(ptr, layout);
+        }
+
+        let free = match global_heap_profiler(&self.config) {
+            // frees of blocks recorded before a stop/pause are still matched.
+            Some(profiler) if profiler.has_live_blocks() || profiler.checks_frees() => {
+                profiler.unregister(ptr, layout)
+            }
+            _ => Free::Forward,
+        };
+
+        // an invalid free is never forwarded, the inner allocator could abort or corrupt its state.
+        match free {
+            Free::Forward => {
+                budget::on_shrink(usage::on_free(layout.size()));
+
+                self.inner.dealloc(ptr, layout);
+            }
+            Free::Quarantine(evicted) => {
+                budget::on_shrink(usage::on_free(layout.size()));
+
+                if let Some((evicted, layout)) = evicted {
+                    self.inner.dealloc(evicted as *mut u8, layout);
+                }
+            }
+            Free::Reject => {}
+        }
+    }
+
+    /// Reallocates the block at `ptr` with the inner allocator, the record of a recorded
+    /// block is moved to the new block.
+    ///
+    /// The block is detached first, like in `on_dealloc`, the inner allocator cannot hand
+    /// out its address again before. A realloc rejected by the free checks fails, it returns
+    /// null and leaves the block untouched. Never inlined, like the other entry points.
+    #[inline(never)]
+    unsafe fn on_realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
         let guard = Reentrancy::new();
 
-        if guard.is_ok() {
-            if let Some(profiler) = global_heap_profiler(self.0) {
-                profiler.unregister(ptr, layout);
+        if !guard.is_ok() {
+            let profiler = initialized_heap_profiler();
+
+            let hidden = profiler.is_some_and(|profiler| profiler.skip_free(ptr));
+
+            let new_ptr = self.inner.realloc(ptr, layout, new_size);
+
+            if let Some(profiler) = profiler {
+                profiler.skip(if new_ptr.is_null() { ptr } else { new_ptr }, hidden);
+            }
+
+            if !new_ptr.is_null() {
+                _ = usage::on_realloc(layout.size(), new_size);
             }
+
+            return new_ptr;
+        }
+
+        // blocks recorded before a stop/pause are still moved.
+        let profiler = global_heap_profiler(&self.config)
+            .filter(|profiler| profiler.has_live_blocks() || profiler.checks_frees());
+
+        let moved = match profiler {
+            Some(profiler) => profiler.detach(ptr, layout),
+            None => Move::Unrecorded { hidden: false },
+        };
+
+        // an invalid realloc is never forwarded, the inner allocator could free `ptr` again.
+        if let Move::Reject = moved {
+            return null_mut();
         }
+
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+
+        if let Some(profiler) = profiler {
+            // a realloc is never a new allocation: a recorded block is resized and counted
+            // in `realloc_objects`, a block that was not recorded, e.g. not sampled, stays so.
+            match moved {
+                Move::Recorded(block) => profiler.attach(ptr, new_ptr, block, new_size),
+                Move::Unrecorded { hidden } if new_ptr.is_null() => profiler.skip(ptr, hidden),
+                Move::Unrecorded { hidden } => profiler.skip(new_ptr, hidden),
+                Move::Reject => {}
+            }
+        }
+
+        // on failure the original block is left untouched.
+        if new_ptr.is_null() {
+            return new_ptr;
+        }
+
+        match usage::on_realloc(layout.size(), new_size) {
+            Ok(live) => budget::on_grow(live),
+            Err(live) => budget::on_shrink(live),
+        }
+
+        new_ptr
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for PprofAlloc<A> {
+    #[inline]
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        self.on_alloc(self.inner.alloc(layout), layout, AllocEntry::Alloc)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.on_alloc(
+            self.inner.alloc_zeroed(layout),
+            layout,
+            AllocEntry::AllocZeroed,
+        )
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.on_realloc(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        self.on_dealloc(ptr, layout);
     }
 }
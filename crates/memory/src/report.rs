@@ -1,14 +1,87 @@
-use std::{collections::HashMap, fs, time::SystemTime};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicU8, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    initialized_heap_profiler, mappings, output,
+    sampler::unsample,
+    stacks::{StackStats, LIFETIME_BUCKETS},
+    Block, Symbol,
+};
+
+use crate::helper::Reentrancy;
 
-use chrono::{DateTime, Local};
-use protobuf::Message;
+use super::proto::gperf as proto;
 
-use crate::{global_heap_profiler, helper::Reentrancy, Symbol};
+pub use proto::Profile;
+
+/// How live blocks are turned into samples.
+#[repr(u8)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReportMode {
+    /// One sample per live block, labeled with its address, allocating thread,
+    /// user-defined labels and `age` in seconds.
+    #[default]
+    Blocks = 0,
+    /// One sample per call stack and age bucket, labeled with `age_bucket`,
+    /// to tell long-lived caches from slowly leaking allocations.
+    AgeBuckets = 1,
+    /// As [`Blocks`](ReportMode::Blocks), without the blocks leaked on purpose,
+    /// see [`mark_leaked`](crate::mark_leaked).
+    Leaks = 2,
+    /// One sample per call stack and allocating thread, labeled with `thread_id` and
+    /// `thread_name`, see [`thread_stats`](crate::thread_stats) for the totals.
+    Threads = 3,
+}
 
-use super::proto::gperf as proto;
+static REPORT_MODE: AtomicU8 = AtomicU8::new(ReportMode::Blocks as u8);
+
+/// Sets the [`ReportMode`] of the following snapshots.
+pub fn set_report_mode(mode: ReportMode) {
+    REPORT_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Returns the current [`ReportMode`].
+pub fn report_mode() -> ReportMode {
+    match REPORT_MODE.load(Ordering::Relaxed) {
+        1 => ReportMode::AgeBuckets,
+        2 => ReportMode::Leaks,
+        3 => ReportMode::Threads,
+        _ => ReportMode::Blocks,
+    }
+}
+
+/// Upper bounds of the age buckets of [`ReportMode::AgeBuckets`].
+const AGE_BUCKETS: [(Duration, &str); 5] = [
+    (Duration::from_secs(1), "<1s"),
+    (Duration::from_secs(10), "1s-10s"),
+    (Duration::from_secs(60), "10s-1m"),
+    (Duration::from_secs(600), "1m-10m"),
+    (Duration::from_secs(3600), "10m-1h"),
+];
+
+/// Returns the name of the age bucket of a block allocated `age` ago.
+pub(crate) fn age_bucket(age: Duration) -> &'static str {
+    AGE_BUCKETS
+        .iter()
+        .find(|(limit, _)| age < *limit)
+        .map(|(_, name)| *name)
+        .unwrap_or(">=1h")
+}
+
+/// The value of a sample label.
+pub(crate) enum LabelValue<'a> {
+    Str(&'a str),
+    Num(i64),
+}
 
 struct FnTable {
-    index: HashMap<usize, u64>,
+    /// Functions by interned name and file name, inlined functions have no address of their own.
+    index: HashMap<(i64, i64), u64>,
     funcs: Vec<proto::Function>,
 }
 
@@ -20,37 +93,70 @@ impl FnTable {
         }
     }
 
-    fn get(&self, symbol: &Symbol) -> Option<u64> {
-        self.index
-            .get(&(symbol.address as usize))
-            .map(|value| *value)
-    }
+    /// Returns the id of the function of `symbol`, added on first use.
+    fn insert(&mut self, string_table: &mut StringTable, symbol: &Symbol) -> u64 {
+        let name = string_table.insert(&symbol.name);
+        let filename = string_table.insert(&symbol.file_name);
+
+        if let Some(func_id) = self.index.get(&(name, filename)) {
+            return *func_id;
+        }
 
-    fn push(&mut self, string_table: &mut StringTable, symbol: &Symbol) -> u64 {
         let func_id = (self.funcs.len() + 1) as u64;
 
         let func = proto::Function {
             id: func_id,
             name: 0,
-            system_name: string_table.insert(&symbol.name),
-            filename: string_table.insert(&symbol.file_name),
+            system_name: name,
+            filename,
             start_line: symbol.line_no as i64,
             ..Default::default()
         };
 
         self.funcs.push(func);
 
-        assert!(
-            self.index
-                .insert(symbol.address as usize, func_id)
-                .is_none(),
-            "push function twice."
-        );
+        self.index.insert((name, filename), func_id);
 
         func_id
     }
 }
 
+/// The executable mappings of the process, sorted by address.
+struct MappingTable {
+    mappings: Vec<proto::Mapping>,
+}
+
+impl MappingTable {
+    fn new(string_table: &mut StringTable) -> Self {
+        let mappings = mappings::read_mappings()
+            .into_iter()
+            .enumerate()
+            .map(|(index, mapping)| proto::Mapping {
+                id: (index + 1) as u64,
+                memory_start: mapping.start,
+                memory_limit: mapping.limit,
+                file_offset: mapping.offset,
+                filename: string_table.insert(&mapping.path),
+                build_id: string_table.insert(&mapping.build_id),
+                ..Default::default()
+            })
+            .collect();
+
+        Self { mappings }
+    }
+
+    /// Returns the mapping of `address`, if any.
+    fn get(&mut self, address: u64) -> Option<&mut proto::Mapping> {
+        let index = self
+            .mappings
+            .partition_point(|mapping| mapping.memory_start <= address);
+
+        self.mappings[..index]
+            .last_mut()
+            .filter(|mapping| address < mapping.memory_limit)
+    }
+}
+
 struct StringTable {
     index: HashMap<String, usize>,
     table: Vec<String>,
@@ -67,137 +173,493 @@ impl StringTable {
     /// Insert new string value and returns offset.
     fn insert(&mut self, value: &str) -> i64 {
         if let Some(offset) = self.index.get(value) {
-            return *offset as i64;
+            *offset as i64
         } else {
             let offset = self.table.len();
             self.table.push(value.to_string());
             self.index.insert(value.to_string(), offset);
-            return offset as i64;
+            offset as i64
         }
     }
 }
+/// The same sample types as go's heap profile, followed by the reallocation count.
+const HEAP_SAMPLE_TYPES: [(&str, &str); 5] = [
+    ("alloc_objects", "count"),
+    ("alloc_space", "bytes"),
+    ("inuse_objects", "count"),
+    ("inuse_space", "bytes"),
+    ("realloc_objects", "count"),
+];
+
+/// The sample types of the lifetime profile, see [`short_lived_snapshot`].
+const LIFETIME_SAMPLE_TYPES: [(&str, &str); 3] = [
+    ("short_lived_objects", "count"),
+    ("short_lived_space", "bytes"),
+    ("freed_objects", "count"),
+];
+
 /// a [`HeapProfilerReport`] implementation that converts sample data to google perftools format.
 pub(crate) struct GperfHeapProfilerReport {
+    sample_rate: usize,
+    sample_types: &'static [(&'static str, &'static str)],
+    default_sample_type: &'static str,
     string_table: StringTable,
     func_table: FnTable,
+    mapping_table: MappingTable,
+    /// Locations by instruction pointer.
+    loc_index: HashMap<usize, u64>,
     loc_table: Vec<proto::Location>,
     samples: Vec<proto::Sample>,
+    comments: Vec<i64>,
 }
 
 impl GperfHeapProfilerReport {
-    pub fn new() -> Self {
+    /// Create a new report, `sample_rate` is the sampling rate the recorded blocks were sampled with.
+    pub fn new(sample_rate: usize) -> Self {
+        let mut string_table = StringTable::new();
+
+        let mapping_table = MappingTable::new(&mut string_table);
+
         Self {
-            string_table: StringTable::new(),
+            sample_rate,
+            sample_types: &HEAP_SAMPLE_TYPES,
+            default_sample_type: "inuse_space",
+            string_table,
             func_table: FnTable::new(),
+            mapping_table,
+            loc_index: Default::default(),
             loc_table: Default::default(),
             samples: Default::default(),
+            comments: Default::default(),
+        }
+    }
+
+    /// Create a new lifetime report, see [`report_lifetime_info`](Self::report_lifetime_info).
+    pub fn lifetimes(sample_rate: usize) -> Self {
+        Self {
+            sample_types: &LIFETIME_SAMPLE_TYPES,
+            default_sample_type: "short_lived_objects",
+            ..Self::new(sample_rate)
         }
     }
 
     pub fn build(&mut self) -> proto::Profile {
-        let samples_value = proto::ValueType {
+        let sample_type = self
+            .sample_types
+            .iter()
+            .map(|(type_, unit)| proto::ValueType {
+                type_: self.string_table.insert(type_),
+                unit: self.string_table.insert(unit),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let period_type = proto::ValueType {
             type_: self.string_table.insert("space"),
             unit: self.string_table.insert("bytes"),
             ..Default::default()
         };
 
+        let default_sample_type = self.string_table.insert(self.default_sample_type);
+
         proto::Profile {
-            sample_type: vec![samples_value],
+            sample_type,
+            default_sample_type,
+            period_type: Some(period_type).into(),
+            period: self.sample_rate as i64,
             sample: self.samples.drain(..).collect::<Vec<_>>(),
             string_table: self.string_table.table.drain(..).collect::<Vec<_>>(),
             function: self.func_table.funcs.drain(..).collect::<Vec<_>>(),
+            mapping: self.mapping_table.mappings.drain(..).collect::<Vec<_>>(),
             location: self.loc_table.drain(..).collect::<Vec<_>>(),
+            comment: self.comments.drain(..).collect::<Vec<_>>(),
             ..Default::default()
         }
     }
 }
 
 impl GperfHeapProfilerReport {
+    /// Add a free-form comment, displayed as is by pprof.
+    pub(crate) fn report_comment(&mut self, comment: &str) {
+        let comment = self.string_table.insert(comment);
+        self.comments.push(comment);
+    }
+
+    /// Report a live block, as the `inuse_objects`/`inuse_space` values of a sample.
+    ///
+    /// The sample is labeled with the block address, its age, the allocating thread
+    /// and the user-defined `labels` set when the block was allocated.
     pub(crate) fn report_block_info(
         &mut self,
-        block: *mut u8,
-        block_size: usize,
-        frames: &[Symbol],
+        ptr: *mut u8,
+        block: &Block,
+        age: Duration,
+        (thread_id, thread_name): (u64, &str),
+        labels: &[(String, String)],
+        frames: &[Vec<Symbol>],
     ) -> bool {
-        let mut locs = vec![];
+        let locs = self.locations(frames);
 
-        for symbol in frames {
-            if let Some(func_id) = self.func_table.get(symbol) {
-                if func_id == 0 {
-                    continue;
-                }
-                locs.push(func_id);
-                continue;
-            }
+        let heap_name = proto::Label {
+            key: self.string_table.insert("block"),
+            str: self.string_table.insert(&format!("0x{:02x}", ptr as usize)),
+            ..Default::default()
+        };
 
-            let func_id = self.func_table.push(&mut self.string_table, symbol);
+        let thread_id = proto::Label {
+            key: self.string_table.insert("thread_id"),
+            num: thread_id as i64,
+            ..Default::default()
+        };
 
-            locs.push(func_id);
+        let thread_name = proto::Label {
+            key: self.string_table.insert("thread_name"),
+            str: self.string_table.insert(thread_name),
+            ..Default::default()
+        };
+
+        let (objects, space) = unsample(self.sample_rate, 1, block.size);
 
-            let line = proto::Line {
-                function_id: func_id,
-                line: symbol.line_no as i64,
+        let age = proto::Label {
+            key: self.string_table.insert("age"),
+            num: age.as_secs() as i64,
+            num_unit: self.string_table.insert("seconds"),
+            ..Default::default()
+        };
+
+        let mut label = vec![heap_name, age, thread_id, thread_name];
+
+        for (key, value) in labels {
+            label.push(proto::Label {
+                key: self.string_table.insert(key),
+                str: self.string_table.insert(value),
                 ..Default::default()
-            };
+            });
+        }
 
-            let loc = proto::Location {
-                id: func_id,
-                line: vec![line],
-                address: symbol.address as u64,
+        let sample = proto::Sample {
+            location_id: locs,
+            label,
+            value: vec![0, 0, objects, space, 0],
+            ..Default::default()
+        };
+
+        self.samples.push(sample);
+
+        true
+    }
+
+    /// Report `count` live blocks of one call stack totalling `bytes`, grouped under `labels`,
+    /// as the `inuse_objects`/`inuse_space` values of a sample.
+    pub(crate) fn report_group_info(
+        &mut self,
+        labels: &[(&str, LabelValue<'_>)],
+        count: usize,
+        bytes: usize,
+        frames: &[Vec<Symbol>],
+    ) {
+        let locs = self.locations(frames);
+
+        let label = labels
+            .iter()
+            .map(|(key, value)| match value {
+                LabelValue::Str(value) => proto::Label {
+                    key: self.string_table.insert(key),
+                    str: self.string_table.insert(value),
+                    ..Default::default()
+                },
+                LabelValue::Num(value) => proto::Label {
+                    key: self.string_table.insert(key),
+                    num: *value,
+                    ..Default::default()
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let (objects, space) = unsample(self.sample_rate, count, bytes);
+
+        let sample = proto::Sample {
+            location_id: locs,
+            label,
+            value: vec![0, 0, objects, space, 0],
+            ..Default::default()
+        };
+
+        self.samples.push(sample);
+    }
+
+    /// Report the cumulative allocations of one call stack,
+    /// as the `alloc_objects`/`alloc_space`/`realloc_objects` values of a sample.
+    pub(crate) fn report_alloc_info(&mut self, stats: &StackStats, frames: &[Vec<Symbol>]) {
+        let locs = self.locations(frames);
+
+        let (objects, space) = unsample(self.sample_rate, stats.count, stats.bytes);
+
+        // reallocations are scaled with the same ratio as the allocations of the stack.
+        let reallocs = if stats.count == 0 {
+            0
+        } else {
+            (stats.reallocs as f64 * objects as f64 / stats.count as f64) as i64
+        };
+
+        let sample = proto::Sample {
+            location_id: locs,
+            value: vec![objects, space, 0, 0, reallocs],
+            ..Default::default()
+        };
+
+        self.samples.push(sample);
+    }
+
+    /// Report the lifetimes of the blocks freed from one call stack, in a report
+    /// created with [`lifetimes`](Self::lifetimes).
+    ///
+    /// The short-lived blocks are the `short_lived_objects`/`short_lived_space` values
+    /// of one sample, each non-empty histogram bucket the `freed_objects` value of a
+    /// sample labeled with its `lifetime_bucket` and `lifetime` lower bound.
+    pub(crate) fn report_lifetime_info(&mut self, stats: &StackStats, frames: &[Vec<Symbol>]) {
+        let locs = self.locations(frames);
+
+        if stats.short_lived > 0 {
+            let (objects, space) =
+                unsample(self.sample_rate, stats.short_lived, stats.short_lived_bytes);
+
+            self.samples.push(proto::Sample {
+                location_id: locs.clone(),
+                value: vec![objects, space, 0],
                 ..Default::default()
-            };
+            });
+        }
 
-            assert_eq!(self.loc_table.len() + 1, func_id as usize);
+        // freed blocks are scaled with the same ratio as the allocations of the stack.
+        let (objects, _) = unsample(self.sample_rate, stats.count, stats.bytes);
 
-            self.loc_table.push(loc);
+        let scale = if stats.count == 0 {
+            1.0
+        } else {
+            objects as f64 / stats.count as f64
+        };
+
+        for (bucket, freed) in stats.lifetimes.iter().enumerate() {
+            if *freed == 0 {
+                continue;
+            }
+
+            let lower = if bucket == 0 { 0 } else { 1i64 << (bucket - 1) };
+
+            let name = match bucket {
+                0 => "<1us".to_string(),
+                _ if bucket == LIFETIME_BUCKETS - 1 => format!(">={}us", lower),
+                _ => format!("{}us-{}us", lower, lower * 2),
+            };
+
+            let label = vec![
+                proto::Label {
+                    key: self.string_table.insert("lifetime_bucket"),
+                    str: self.string_table.insert(&name),
+                    ..Default::default()
+                },
+                proto::Label {
+                    key: self.string_table.insert("lifetime"),
+                    num: lower,
+                    num_unit: self.string_table.insert("microseconds"),
+                    ..Default::default()
+                },
+            ];
+
+            self.samples.push(proto::Sample {
+                location_id: locs.clone(),
+                label,
+                value: vec![0, 0, (*freed as f64 * scale) as i64],
+                ..Default::default()
+            });
         }
+    }
 
-        let heap_name = proto::Label {
-            key: self.string_table.insert("block"),
-            str: self
-                .string_table
-                .insert(&format!("0x{:02x}", block as usize)),
-            ..Default::default()
+    /// Report the allocations rejected by the size filters, counted without their stack,
+    /// as the `alloc_objects`/`alloc_space` values of a sample at a synthetic `[untracked]` location.
+    pub(crate) fn report_untracked(&mut self, count: usize, bytes: usize) {
+        let untracked = Symbol {
+            name: "[untracked]".into(),
+            // no code lives at this address, it cannot collide with a real location.
+            address: usize::MAX,
+            ip: usize::MAX,
+            file_name: Default::default(),
+            line_no: 0,
+            col_no: 0,
         };
 
+        let locs = self.locations(&[vec![untracked]]);
+
+        // untracked allocations are all counted, they are not scaled.
         let sample = proto::Sample {
             location_id: locs,
-            label: vec![heap_name],
-            value: vec![block_size as i64],
+            value: vec![count as i64, bytes as i64, 0, 0, 0],
             ..Default::default()
         };
 
         self.samples.push(sample);
+    }
 
-        true
+    /// Returns the location ids of `frames`, each frame being the symbols of one
+    /// instruction pointer, the inlined functions first.
+    fn locations(&mut self, frames: &[Vec<Symbol>]) -> Vec<u64> {
+        let mut locs = vec![];
+
+        for symbols in frames {
+            let Some(outer) = symbols.last() else {
+                continue;
+            };
+
+            if let Some(loc_id) = self.loc_index.get(&outer.ip) {
+                locs.push(*loc_id);
+                continue;
+            }
+
+            // one line per inlined function, leaf first as required by the proto,
+            // an unresolved frame is left to pprof to symbolize from its mapping.
+            let line = symbols
+                .iter()
+                .filter(|symbol| !symbol.is_unresolved())
+                .map(|symbol| proto::Line {
+                    function_id: self.func_table.insert(&mut self.string_table, symbol),
+                    line: symbol.line_no as i64,
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>();
+
+            let address = outer.ip as u64;
+
+            // the mapping is resolved in process, pprof does not need its binary.
+            let mapping_id = match self.mapping_table.get(address) {
+                Some(mapping) => {
+                    mapping.has_functions |= symbols.iter().any(|symbol| !symbol.name.is_empty());
+                    mapping.has_filenames |=
+                        symbols.iter().any(|symbol| !symbol.file_name.is_empty());
+                    mapping.has_line_numbers |= symbols.iter().any(|symbol| symbol.line_no != 0);
+                    mapping.has_inline_frames |= symbols.len() > 1;
+                    mapping.id
+                }
+                None => 0,
+            };
+
+            let loc_id = (self.loc_table.len() + 1) as u64;
+
+            self.loc_table.push(proto::Location {
+                id: loc_id,
+                mapping_id,
+                line,
+                address,
+                ..Default::default()
+            });
+
+            self.loc_index.insert(outer.ip, loc_id);
+
+            locs.push(loc_id);
+        }
+
+        locs
     }
 }
 
-/// Dump a new memory profiling report in [`pb format`](https://github.com/google/pprof/tree/main/proto)
-/// to the current working directory.
-pub fn snapshot() {
+/// Returns a new memory profiling report, in the [`ReportMode`] set by [`set_report_mode`].
+///
+/// The profile is empty if no [`PprofAlloc`](crate::PprofAlloc) has served an allocation yet.
+///
+/// ```no_run
+/// use hala_pprof_memory::{snapshot_profile, PprofAlloc};
+///
+/// #[global_allocator]
+/// static ALLOC: PprofAlloc = PprofAlloc::new(10);
+///
+/// fn main() {
+///     let profile = snapshot_profile();
+///
+///     println!("{} samples", profile.sample.len());
+/// }
+/// ```
+pub fn snapshot_profile() -> Profile {
     let _guard = Reentrancy::new();
 
-    if let Some(profiler) = global_heap_profiler(20) {
-        let profile = profiler.report();
+    match initialized_heap_profiler() {
+        Some(profiler) => profiler.report(report_mode()),
+        None => Profile::new(),
+    }
+}
 
-        let buf = profile.write_to_bytes().unwrap();
+/// Returns a new memory profiling report encoded in [`pb format`](https://github.com/google/pprof/tree/main/proto),
+/// gzip-compressed unless disabled by [`SnapshotConfig::with_compression`](crate::SnapshotConfig::with_compression),
+/// see [`snapshot_profile`].
+///
+/// # Panics
+///
+/// If the encoded profile exceeds the 2 GiB limit of protobuf messages.
+pub fn snapshot_bytes() -> Vec<u8> {
+    let profile = snapshot_profile();
 
-        let datetime: DateTime<Local> = SystemTime::now().into();
+    let mut buf = vec![];
 
-        fs::write(
-            format!(
-                "./memory.{}.pprof.pb",
-                datetime
-                    .format("%+")
-                    .to_string()
-                    .replace("-", "_")
-                    .replace(":", "_")
-                    .replace(" ", "_")
-                    .replace("+", "_")
-            ),
-            buf,
-        )
-        .unwrap();
-    }
+    output::encode_profile(&profile, &mut buf, output::compression())
+        .expect("memory profile exceeds the protobuf size limit");
+
+    buf
+}
+
+/// Writes a new memory profiling report in [`pb format`](https://github.com/google/pprof/tree/main/proto)
+/// to `writer`, compressed like [`snapshot_bytes`], see [`snapshot_profile`].
+///
+/// The profile is written as a whole, `writer` is not buffered further.
+pub fn snapshot_to_writer<W: Write>(writer: W) -> io::Result<()> {
+    let profile = snapshot_profile();
+
+    output::encode_profile(&profile, writer, output::compression())
+}
+
+/// Dump a new memory profiling report in [`pb format`](https://github.com/google/pprof/tree/main/proto)
+/// to the current working directory, or as set by [`set_snapshot_config`](crate::set_snapshot_config),
+/// returns the path of the written file.
+pub fn snapshot() -> io::Result<PathBuf> {
+    write_profile("memory", snapshot_profile())
+}
+
+/// Dump the allocations freed shortly after their allocation, per call stack,
+/// like [`snapshot`], returns the path of the written file.
+///
+/// Blocks freed within the threshold set by
+/// [`PprofAlloc::with_short_lived_threshold`](crate::PprofAlloc::with_short_lived_threshold)
+/// are the `short_lived_objects` and `short_lived_space` values, the log-scale
+/// lifetime histogram of all freed blocks the `freed_objects` values labeled by `lifetime_bucket`.
+/// The hot sites with many short-lived blocks are candidates for buffer reuse.
+pub fn short_lived_snapshot() -> io::Result<PathBuf> {
+    let profile = {
+        let _guard = Reentrancy::new();
+
+        match initialized_heap_profiler() {
+            Some(profiler) => profiler.report_lifetimes(),
+            None => Profile::new(),
+        }
+    };
+
+    write_profile("memory.short_lived", profile)
+}
+
+/// Dump the heap as it was at its high-water mark like [`snapshot`],
+/// returns the path of the written file, see [`PprofAlloc::with_peak_margin`](crate::PprofAlloc::with_peak_margin).
+pub fn peak_snapshot() -> io::Result<PathBuf> {
+    let profile = {
+        let _guard = Reentrancy::new();
+
+        match initialized_heap_profiler() {
+            Some(profiler) => profiler.report_peak(),
+            None => Profile::new(),
+        }
+    };
+
+    write_profile("memory.peak", profile)
+}
+
+/// Writes `profile` as configured by [`set_snapshot_config`](crate::set_snapshot_config),
+/// returns the path of the file.
+pub(crate) fn write_profile(prefix: &str, profile: Profile) -> io::Result<PathBuf> {
+    output::write_profile(prefix, &profile)
 }
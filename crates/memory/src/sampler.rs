@@ -0,0 +1,84 @@
+//! Poisson byte-rate allocation sampler.
+//!
+//! The same scheme is used by tcmalloc and go's `runtime.MemProfileRate`:
+//! the distance in bytes between two sampled allocations follows an
+//! exponential distribution whose mean is the sampling rate, so on average
+//! one allocation per `sample_rate` bytes is recorded and the probability
+//! of recording an allocation of `size` bytes is `1 - exp(-size / sample_rate)`.
+
+use crate::helper::{sampler_state, SamplerState};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Returns true if an allocation of `size` bytes should be recorded.
+///
+/// A `sample_rate` of zero disables sampling, every allocation is recorded.
+#[inline]
+pub(crate) fn should_sample(sample_rate: usize, size: usize) -> bool {
+    if sample_rate == 0 {
+        return true;
+    }
+
+    let state = sampler_state();
+
+    if state.rng == 0 {
+        seed(state);
+        state.bytes_until_sample = next_interval(state, sample_rate);
+    }
+
+    if state.bytes_until_sample > size as i64 {
+        state.bytes_until_sample -= size as i64;
+        return false;
+    }
+
+    state.bytes_until_sample = next_interval(state, sample_rate);
+
+    true
+}
+
+/// Returns the `(count, bytes)` estimate of unsampled allocations
+/// represented by `count` sampled allocations totalling `bytes`.
+pub(crate) fn unsample(sample_rate: usize, count: usize, bytes: usize) -> (i64, i64) {
+    if sample_rate == 0 || count == 0 {
+        return (count as i64, bytes as i64);
+    }
+
+    let avg_size = bytes as f64 / count as f64;
+
+    let scale = 1.0 / (1.0 - (-avg_size / sample_rate as f64).exp());
+
+    ((count as f64 * scale) as i64, (bytes as f64 * scale) as i64)
+}
+
+fn seed(state: &mut SamplerState) {
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+
+    // mix the thread-local address with a global sequence, the seed must never be zero.
+    state.rng = splitmix64((state as *mut SamplerState as u64) ^ sequence) | 1;
+}
+
+/// Draws the next sampling distance from an exponential distribution with mean `sample_rate`.
+fn next_interval(state: &mut SamplerState, sample_rate: usize) -> i64 {
+    // xorshift64*
+    state.rng ^= state.rng >> 12;
+    state.rng ^= state.rng << 25;
+    state.rng ^= state.rng >> 27;
+
+    let bits = state.rng.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+
+    // uniform in (0, 1]
+    let uniform = (bits + 1) as f64 / (1u64 << 53) as f64;
+
+    let interval = -uniform.ln() * sample_rate as f64;
+
+    (interval as i64).max(1)
+}
+
+fn splitmix64(mut value: u64) -> u64 {
+    value = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
@@ -0,0 +1,301 @@
+//! Deduplicated table of the captured call stacks.
+
+use std::{
+    hash::{BuildHasher, BuildHasherDefault, DefaultHasher},
+    sync::Mutex,
+};
+
+use hashbrown::HashTable;
+
+use crate::{
+    blocks::lock,
+    metadata::{MetaAlloc, MetaBox, MetaVec},
+};
+
+/// Number of shards, must be a power of two.
+const SHARDS: usize = 64;
+
+/// Identifier of an interned call stack, see [`StackTable`].
+pub(crate) type StackId = usize;
+
+/// Number of buckets of the lifetime histograms.
+pub(crate) const LIFETIME_BUCKETS: usize = 32;
+
+/// Returns the lifetime histogram bucket of a block freed `nanos` after its allocation.
+///
+/// Bucket 0 holds lifetimes under 1us, bucket `i` lifetimes in `[2^(i-1), 2^i)` us,
+/// the last bucket every longer lifetime.
+#[inline]
+pub(crate) fn lifetime_bucket(nanos: u64) -> usize {
+    let micros = nanos / 1000;
+
+    ((u64::BITS - micros.leading_zeros()) as usize).min(LIFETIME_BUCKETS - 1)
+}
+
+/// Statistics of one interned call stack.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct StackStats {
+    /// Number of live blocks allocated from this stack, the reference count of the entry.
+    pub refs: usize,
+    /// Total bytes of the live blocks allocated from this stack.
+    pub live_bytes: usize,
+    /// Number of recorded allocations, including the freed ones.
+    pub count: usize,
+    /// Total bytes of recorded allocations, including the freed ones.
+    pub bytes: usize,
+    /// Number of reallocations of the recorded blocks.
+    pub reallocs: usize,
+    /// Number of freed blocks that lived less than the short-lived threshold.
+    pub short_lived: usize,
+    /// Total bytes of the short-lived blocks.
+    pub short_lived_bytes: usize,
+    /// Log-scale histogram of the lifetimes of the freed blocks, see [`lifetime_bucket`].
+    pub lifetimes: [u32; LIFETIME_BUCKETS],
+}
+
+impl StackStats {
+    fn is_empty(&self) -> bool {
+        self.refs == 0 && self.count == 0 && self.reallocs == 0
+    }
+}
+
+/// An interned call stack.
+struct StackEntry {
+    frames: MetaBox<[usize]>,
+    stats: StackStats,
+}
+
+struct StackShard {
+    /// Slots of the interned stacks, looked up by the hash of their frames.
+    index: HashTable<usize, MetaAlloc>,
+    slots: MetaVec<Option<StackEntry>>,
+    free: MetaVec<usize>,
+}
+
+impl Default for StackShard {
+    fn default() -> Self {
+        Self {
+            index: HashTable::new_in(MetaAlloc),
+            slots: MetaVec::new_in(MetaAlloc),
+            free: MetaVec::new_in(MetaAlloc),
+        }
+    }
+}
+
+#[inline]
+fn hash_frames(frames: &[usize]) -> u64 {
+    BuildHasherDefault::<DefaultHasher>::default().hash_one(frames)
+}
+
+/// Returns the entry of an indexed slot.
+#[inline]
+fn indexed(slots: &[Option<StackEntry>], slot: usize) -> &StackEntry {
+    slots[slot].as_ref().expect("indexed stack")
+}
+
+/// Table of interned call stacks, shared by every block allocated from the same site.
+///
+/// Each entry is reference counted by the live blocks pointing to it, and keeps the
+/// cumulative allocation statistics of its stack until [`reset`](StackTable::reset).
+pub(crate) struct StackTable {
+    shards: [Mutex<StackShard>; SHARDS],
+}
+
+impl Default for StackTable {
+    fn default() -> Self {
+        Self {
+            shards: std::array::from_fn(|_| Mutex::default()),
+        }
+    }
+}
+
+impl StackTable {
+    #[inline]
+    fn shard_of(id: StackId) -> (usize, usize) {
+        (id & (SHARDS - 1), id / SHARDS)
+    }
+
+    /// Records an allocation of `size` bytes from `frames`, returns the id of the stack.
+    ///
+    /// The returned id holds a reference to the entry until [`free`](Self::free) is called.
+    pub(crate) fn alloc(&self, frames: &[usize], size: usize) -> StackId {
+        let hash = hash_frames(frames);
+
+        // the index buckets use the low bits of the hash, the shards its mixed high bits.
+        let shard_index = (hash.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            >> (u64::BITS - SHARDS.trailing_zeros())) as usize;
+
+        let mut shard = lock(&self.shards[shard_index]);
+
+        let StackShard { index, slots, free } = &mut *shard;
+
+        let found = index
+            .find(hash, |slot| *indexed(slots, *slot).frames == *frames)
+            .copied();
+
+        let slot = match found {
+            Some(slot) => slot,
+            None => {
+                let entry = StackEntry {
+                    frames: MetaBox::from(frames),
+                    stats: StackStats::default(),
+                };
+
+                let slot = match free.pop() {
+                    Some(slot) => {
+                        slots[slot] = Some(entry);
+                        slot
+                    }
+                    None => {
+                        slots.push(Some(entry));
+                        slots.len() - 1
+                    }
+                };
+
+                index.insert_unique(hash, slot, |slot| {
+                    hash_frames(&indexed(slots, *slot).frames)
+                });
+
+                slot
+            }
+        };
+
+        let stats = &mut slots[slot].as_mut().expect("interned stack").stats;
+
+        stats.refs += 1;
+        stats.live_bytes += size;
+        stats.count += 1;
+        stats.bytes += size;
+
+        slot * SHARDS + shard_index
+    }
+
+    /// Records the free of a block of `size` bytes, releasing its reference to the stack.
+    pub(crate) fn free(&self, id: StackId, size: usize) {
+        let (shard_index, slot) = Self::shard_of(id);
+
+        let mut shard = lock(&self.shards[shard_index]);
+
+        if let Some(Some(StackEntry { stats, .. })) = shard.slots.get_mut(slot) {
+            stats.refs -= 1;
+            stats.live_bytes -= size;
+        }
+    }
+
+    /// Records the free of a block of `size` bytes, `lifetime` nanoseconds after its allocation,
+    /// releasing its reference to the stack.
+    pub(crate) fn free_after(&self, id: StackId, size: usize, lifetime: u64, short_lived: bool) {
+        let (shard_index, slot) = Self::shard_of(id);
+
+        let mut shard = lock(&self.shards[shard_index]);
+
+        if let Some(Some(StackEntry { stats, .. })) = shard.slots.get_mut(slot) {
+            stats.refs -= 1;
+            stats.live_bytes -= size;
+
+            let bucket = &mut stats.lifetimes[lifetime_bucket(lifetime)];
+            *bucket = bucket.saturating_add(1);
+
+            if short_lived {
+                stats.short_lived += 1;
+                stats.short_lived_bytes += size;
+            }
+        }
+    }
+
+    /// Records the reallocation of a block from `old_size` to `new_size` bytes.
+    pub(crate) fn realloc(&self, id: StackId, old_size: usize, new_size: usize) {
+        let (shard_index, slot) = Self::shard_of(id);
+
+        let mut shard = lock(&self.shards[shard_index]);
+
+        if let Some(Some(StackEntry { stats, .. })) = shard.slots.get_mut(slot) {
+            stats.live_bytes = stats.live_bytes - old_size + new_size;
+            stats.reallocs += 1;
+        }
+    }
+
+    /// Clears the cumulative statistics, and drops the stacks no live block refers to.
+    pub(crate) fn reset(&self) {
+        for shard in self.shards.iter() {
+            let mut shard = lock(shard);
+
+            let StackShard { index, slots, free } = &mut *shard;
+
+            for StackEntry { stats, .. } in slots.iter_mut().flatten() {
+                stats.count = 0;
+                stats.bytes = 0;
+                stats.reallocs = 0;
+                stats.short_lived = 0;
+                stats.short_lived_bytes = 0;
+                stats.lifetimes = Default::default();
+            }
+
+            index.retain(|slot| {
+                if indexed(slots, *slot).stats.is_empty() {
+                    slots[*slot] = None;
+                    free.push(*slot);
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+
+    /// Returns a copy of the frames of the stack `id`, empty if it is not interned.
+    pub(crate) fn frames(&self, id: StackId) -> MetaBox<[usize]> {
+        let (shard_index, slot) = Self::shard_of(id);
+
+        let shard = lock(&self.shards[shard_index]);
+
+        match shard.slots.get(slot) {
+            Some(Some(entry)) => MetaBox::from(&*entry.frames),
+            _ => MetaBox::from(&[][..]),
+        }
+    }
+
+    /// Returns the `(id, live blocks, live bytes)` of every stack with live blocks.
+    #[cfg(feature = "report")]
+    pub(crate) fn live_totals(&self) -> MetaVec<(StackId, usize, usize)> {
+        let mut totals = MetaVec::new_in(MetaAlloc);
+
+        for (shard_index, shard) in self.shards.iter().enumerate() {
+            let shard = lock(shard);
+
+            for (slot, entry) in shard.slots.iter().enumerate() {
+                if let Some(stats) = entry
+                    .as_ref()
+                    .map(|entry| entry.stats)
+                    .filter(|stats| stats.refs > 0)
+                {
+                    totals.push((slot * SHARDS + shard_index, stats.refs, stats.live_bytes));
+                }
+            }
+        }
+
+        totals
+    }
+
+    /// Returns a copy of every interned stack, with its id and statistics.
+    #[cfg(feature = "report")]
+    pub(crate) fn entries(&self) -> Vec<(StackId, Vec<usize>, StackStats)> {
+        let mut entries = vec![];
+
+        for (shard_index, shard) in self.shards.iter().enumerate() {
+            let shard = lock(shard);
+
+            for (slot, entry) in shard.slots.iter().enumerate() {
+                if let Some(entry) = entry {
+                    entries.push((
+                        slot * SHARDS + shard_index,
+                        entry.frames.to_vec(),
+                        entry.stats,
+                    ));
+                }
+            }
+        }
+
+        entries
+    }
+}
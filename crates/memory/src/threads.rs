@@ -0,0 +1,193 @@
+//! Table of the threads that allocated recorded blocks, with their heap accounting.
+
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{
+    blocks::lock,
+    helper::{thread_id, thread_index, thread_name, Reentrancy},
+    initialized_heap_profiler,
+    metadata::{MetaAlloc, MetaHashMap, MetaVec},
+};
+
+/// Maximum length of a recorded thread name, linux limits names to 15 bytes.
+const MAX_THREAD_NAME: usize = 64;
+
+/// Number of table shards, threads are spread round-robin by their index.
+const SHARDS: usize = 64;
+
+/// Heap accounting of one thread, see [`thread_stats`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ThreadStats {
+    /// The operating system thread id.
+    pub id: u64,
+    /// The thread name, empty if the thread is not named.
+    pub name: String,
+    /// Bytes allocated by the thread and not freed yet, by any thread.
+    pub live_bytes: i64,
+    /// Number of blocks allocated by the thread and not freed yet, by any thread.
+    pub live_blocks: i64,
+    /// Bytes allocated by the thread, including the growth of its reallocated blocks.
+    pub allocated_bytes: i64,
+    /// Number of allocations of the thread.
+    pub allocations: i64,
+}
+
+/// Index of a thread in the [`ThreadTable`], unique for the life of the process.
+pub(crate) type ThreadIndex = usize;
+
+struct ThreadEntry {
+    /// The operating system thread id, which may be reused by a later thread.
+    id: u64,
+    name: MetaVec<u8>,
+    live_bytes: i64,
+    live_blocks: i64,
+    allocated_bytes: i64,
+    allocations: i64,
+}
+
+type ThreadShard = MetaHashMap<ThreadIndex, ThreadEntry>;
+
+/// The threads seen by the profiler, keyed by [`thread_index`], which unlike
+/// operating system ids is never reused.
+///
+/// The id and name of a thread are read once, by its first recorded allocation.
+/// Each thread updates the entry of its own shard, the entries are only merged
+/// when they are read. Threads are kept after they exit, the blocks they allocated
+/// may still be live.
+pub(crate) struct ThreadTable {
+    shards: [Mutex<ThreadShard>; SHARDS],
+}
+
+impl Default for ThreadTable {
+    fn default() -> Self {
+        Self {
+            shards: std::array::from_fn(|_| Default::default()),
+        }
+    }
+}
+
+impl ThreadTable {
+    fn shard(&self, index: ThreadIndex) -> MutexGuard<'_, ThreadShard> {
+        lock(&self.shards[index & (SHARDS - 1)])
+    }
+
+    /// Records an allocation of the calling thread, estimated as `count` blocks
+    /// of `bytes` bytes, returns the thread index.
+    pub(crate) fn alloc(&self, count: i64, bytes: i64) -> ThreadIndex {
+        let index = thread_index();
+
+        let mut shard = self.shard(index);
+
+        let entry = shard.entry(index).or_insert_with(|| {
+            let mut buf = [0u8; MAX_THREAD_NAME];
+
+            let mut name = MetaVec::new_in(MetaAlloc);
+
+            name.extend_from_slice(thread_name(&mut buf));
+
+            ThreadEntry {
+                id: thread_id(),
+                name,
+                live_bytes: 0,
+                live_blocks: 0,
+                allocated_bytes: 0,
+                allocations: 0,
+            }
+        });
+
+        entry.live_bytes += bytes;
+        entry.live_blocks += count;
+        entry.allocated_bytes += bytes;
+        entry.allocations += count;
+
+        index
+    }
+
+    /// Records the free of blocks allocated by the thread `index`, estimated as `count` blocks of `bytes` bytes.
+    pub(crate) fn free(&self, index: ThreadIndex, count: i64, bytes: i64) {
+        if let Some(entry) = self.shard(index).get_mut(&index) {
+            entry.live_bytes -= bytes;
+            entry.live_blocks -= count;
+        }
+    }
+
+    /// Records the resize of a block allocated by the thread `index`, from `old_bytes` to `new_bytes` estimated bytes.
+    pub(crate) fn realloc(&self, index: ThreadIndex, old_bytes: i64, new_bytes: i64) {
+        if let Some(entry) = self.shard(index).get_mut(&index) {
+            entry.live_bytes += new_bytes - old_bytes;
+            entry.allocated_bytes += (new_bytes - old_bytes).max(0);
+        }
+    }
+
+    /// Clears the accounting of every thread, the names are kept.
+    pub(crate) fn reset(&self) {
+        for shard in &self.shards {
+            for entry in lock(shard).values_mut() {
+                entry.live_bytes = 0;
+                entry.live_blocks = 0;
+                entry.allocated_bytes = 0;
+                entry.allocations = 0;
+            }
+        }
+    }
+
+    /// Returns the operating system id and the name of every thread, keyed by thread index.
+    #[cfg(feature = "report")]
+    pub(crate) fn names(&self) -> Vec<(ThreadIndex, (u64, String))> {
+        let mut names = vec![];
+
+        for shard in &self.shards {
+            for (index, entry) in lock(shard).iter() {
+                names.push((
+                    *index,
+                    (entry.id, String::from_utf8_lossy(&entry.name).into_owned()),
+                ));
+            }
+        }
+
+        names
+    }
+
+    /// Returns a copy of the accounting of every thread.
+    pub(crate) fn stats(&self) -> Vec<ThreadStats> {
+        let mut stats = vec![];
+
+        for shard in &self.shards {
+            for entry in lock(shard).values() {
+                stats.push(ThreadStats {
+                    id: entry.id,
+                    name: String::from_utf8_lossy(&entry.name).into_owned(),
+                    live_bytes: entry.live_bytes,
+                    live_blocks: entry.live_blocks,
+                    allocated_bytes: entry.allocated_bytes,
+                    allocations: entry.allocations,
+                });
+            }
+        }
+
+        stats
+    }
+}
+
+/// Returns the heap accounting of every thread that allocated recorded blocks,
+/// sorted by decreasing live bytes.
+///
+/// A block freed by another thread than its allocator is accounted to its allocator.
+/// The values cover the recorded blocks since the last [`start`](crate::start) or
+/// [`reset`](crate::reset), scaled to estimates of the unsampled heap like the profiles.
+pub fn thread_stats() -> Vec<ThreadStats> {
+    let Some(profiler) = initialized_heap_profiler() else {
+        return vec![];
+    };
+
+    let mut stats = {
+        // the copy allocates, which must not be recorded.
+        let _guard = Reentrancy::new();
+
+        profiler.threads().stats()
+    };
+
+    stats.sort_by_key(|stats| std::cmp::Reverse(stats.live_bytes));
+
+    stats
+}
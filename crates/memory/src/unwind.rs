@@ -0,0 +1,299 @@
+//! Call stack unwinders.
+
+use std::{
+    ffi::c_void,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    helper::{backtrace_lock, symbolize_lock},
+    metadata::{MetaAlloc, MetaBox, MetaVec},
+};
+
+/// A strategy to walk the call stack of the current thread.
+///
+/// Implementations are called from inside the global allocator, they may allocate,
+/// such allocations are not recorded, but must not panic.
+pub trait StackUnwinder: Sync {
+    /// Calls `f` with the instruction pointer of every frame of the current thread,
+    /// from the innermost frame outwards, until `f` returns false or the stack ends.
+    fn unwind(&self, f: &mut dyn FnMut(usize) -> bool);
+
+    /// Whether the walks must be serialized process-wide, true by default as the
+    /// platform unwinder is not thread-safe.
+    fn needs_lock(&self) -> bool {
+        true
+    }
+}
+
+/// The default [`StackUnwinder`], based on [`backtrace::trace_unsynchronized`].
+///
+/// It unwinds with the platform unwinder and the DWARF unwind tables, which works
+/// for any binary but costs a few microseconds per frame.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BacktraceUnwinder;
+
+impl StackUnwinder for BacktraceUnwinder {
+    #[inline(never)]
+    fn unwind(&self, f: &mut dyn FnMut(usize) -> bool) {
+        unsafe {
+            backtrace::trace_unsynchronized(|frame| f(frame.ip() as usize));
+        }
+    }
+}
+
+/// A [`StackUnwinder`] following the frame pointer chain, much cheaper than [`BacktraceUnwinder`].
+///
+/// Every frame of the walked stack must keep its frame pointer, build with
+/// `RUSTFLAGS="-C force-frame-pointers=yes"`, otherwise stacks are truncated or wrong.
+/// The walk stops at the first frame outside the thread's stack, so a broken chain is
+/// never followed into unmapped memory.
+///
+/// On windows and on targets other than `x86_64` and `aarch64`, where frame records are
+/// not chained, or where the stack bounds of a thread are not known, it falls back to
+/// [`BacktraceUnwinder`].
+///
+/// Unlike [`BacktraceUnwinder`], concurrent walks do not wait for each other.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FramePointerUnwinder;
+
+#[cfg(all(
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    not(target_os = "windows")
+))]
+impl StackUnwinder for FramePointerUnwinder {
+    #[inline(never)]
+    fn unwind(&self, f: &mut dyn FnMut(usize) -> bool) {
+        let Some((low, high)) = crate::helper::stack_bounds() else {
+            // the caller does not serialize the walks, see `needs_lock`.
+            let _locker = backtrace_lock();
+
+            return BacktraceUnwinder.unwind(f);
+        };
+
+        let mut fp: usize;
+
+        unsafe {
+            #[cfg(target_arch = "x86_64")]
+            std::arch::asm!("mov {}, rbp", out(reg) fp, options(nomem, nostack, preserves_flags));
+
+            #[cfg(target_arch = "aarch64")]
+            std::arch::asm!("mov {}, x29", out(reg) fp, options(nomem, nostack, preserves_flags));
+        }
+
+        // every frame record of the callers is above this local, nothing below it is in use.
+        let marker = 0usize;
+        let low = low.max(std::hint::black_box(&marker) as *const usize as usize);
+
+        let record_size = 2 * std::mem::size_of::<usize>();
+
+        // each frame record is `[previous frame pointer, return address]`.
+        while fp >= low
+            && fp <= high.saturating_sub(record_size)
+            && fp & (std::mem::align_of::<usize>() - 1) == 0
+        {
+            let (next, ip) = unsafe {
+                let record = fp as *const usize;
+                (*record, *record.add(1))
+            };
+
+            if ip == 0 || !f(ip) {
+                break;
+            }
+
+            // the stack grows downwards, a caller frame is always above its callee.
+            if next <= fp {
+                break;
+            }
+
+            fp = next;
+        }
+    }
+
+    fn needs_lock(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(not(all(
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    not(target_os = "windows")
+)))]
+impl StackUnwinder for FramePointerUnwinder {
+    #[inline(never)]
+    fn unwind(&self, f: &mut dyn FnMut(usize) -> bool) {
+        BacktraceUnwinder.unwind(f)
+    }
+}
+
+/// Call this fn to get callstack, not via [`backtrace::trace`].
+///
+/// The [``backtrace``] standard api, which uses thread-local keys, may not use in GlobalAlloc.
+///
+/// The `skip` innermost frames are dropped, at most `max_frames` frames are returned.
+#[inline(never)]
+pub(crate) fn get_backtrace(
+    unwinder: &dyn StackUnwinder,
+    skip: usize,
+    max_frames: usize,
+) -> MetaVec<usize> {
+    let mut stack = MetaVec::with_capacity_in(max_frames, MetaAlloc);
+
+    let mut skips = 0;
+
+    unwinder.unwind(&mut |ip| {
+        if skips < skip {
+            skips += 1;
+            return true;
+        }
+
+        stack.push(ip);
+
+        stack.len() < max_frames
+    });
+
+    stack
+}
+
+/// Returns the function segment of a demangled `name`, without the legacy mangling hash.
+fn function_name(name: &str) -> Option<&str> {
+    let mut segments = name.rsplit("::");
+
+    match segments.next() {
+        Some(segment) if segment.starts_with('h') && segment.len() == 17 => segments.next(),
+        segment => segment,
+    }
+}
+
+/// Returns true if `name` is a frame of the allocator machinery:
+/// the `GlobalAlloc` shims generated by rustc and the `GlobalAlloc` implementations.
+fn is_allocator_frame(name: &str) -> bool {
+    if name.contains("GlobalAlloc>::") {
+        return true;
+    }
+
+    // the shims may be prefixed by a crate path, e.g. `__rustc[..]::__rust_alloc`.
+    function_name(name).is_some_and(|function| {
+        [
+            "__rust_alloc",
+            "__rust_realloc",
+            "__rust_dealloc",
+            "__rg_",
+            "__rdl_",
+        ]
+        .iter()
+        .any(|shim| function.starts_with(shim))
+    })
+}
+
+/// Returns true if `name` is the profiler entry point called by the `GlobalAlloc` methods.
+fn is_entry_frame(name: &str) -> bool {
+    name.contains("hala_pprof_memory::profiler::PprofAlloc")
+        && matches!(
+            function_name(name),
+            Some("on_alloc" | "on_dealloc" | "on_realloc")
+        )
+}
+
+/// Returns the name of the outermost function at `ip`, the one the inlined functions are inlined into.
+fn outermost_name(ip: usize) -> Option<String> {
+    let mut outermost = None;
+
+    unsafe {
+        backtrace::resolve_unsynchronized(ip as *mut c_void, |symbol| {
+            outermost = symbol.name().map(|name| name.to_string());
+        });
+    }
+
+    outermost
+}
+
+/// Returns the number of innermost frames of `frames` up to the profiler entry point,
+/// `frames` being captured from inside `GlobalAlloc`.
+///
+/// The entry points are never inlined, the count is the same for every capture.
+/// Returns `None` if the entry point is not found, e.g. when the binary has no symbols.
+pub(crate) fn detect_skip_frames(frames: &[usize]) -> Option<usize> {
+    frames
+        .iter()
+        .position(|ip| outermost_name(*ip).is_some_and(|name| is_entry_frame(&name)))
+        .map(|index| index + 1)
+}
+
+/// Returns true if the frame at `ip` is an allocator shim or a `GlobalAlloc` implementation.
+///
+/// These frames follow the profiler entry point, unless they are inlined into the caller,
+/// which depends on the call site.
+fn is_allocator_ip(ip: usize) -> bool {
+    outermost_name(ip).is_some_and(|name| is_allocator_frame(&name))
+}
+
+/// Number of slots of the [`AllocatorIps`] cache, must be a power of two.
+const ALLOCATOR_IPS: usize = 4096;
+
+/// Number of slots probed for an address, an address that finds no slot is not cached.
+const ALLOCATOR_IPS_PROBES: usize = 16;
+
+/// Lock-free cache of the addresses known to be, or not to be, allocator frames.
+///
+/// Each slot holds `ip << 1 | is_allocator`, zero when empty, and is never cleared.
+pub(crate) struct AllocatorIps {
+    slots: MetaBox<[AtomicU64]>,
+}
+
+impl Default for AllocatorIps {
+    fn default() -> Self {
+        let mut slots = MetaVec::with_capacity_in(ALLOCATOR_IPS, MetaAlloc);
+
+        slots.resize_with(ALLOCATOR_IPS, || AtomicU64::new(0));
+
+        Self {
+            slots: slots.into_boxed_slice(),
+        }
+    }
+}
+
+impl AllocatorIps {
+    /// Returns the slots `ip` may be cached in.
+    fn probe(&self, ip: usize) -> impl Iterator<Item = &AtomicU64> {
+        // fibonacci hashing, the low bits of addresses are mostly alignment.
+        let start = ((ip as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            >> (u64::BITS - ALLOCATOR_IPS.trailing_zeros())) as usize;
+
+        (start..start + ALLOCATOR_IPS_PROBES).map(|index| &self.slots[index & (ALLOCATOR_IPS - 1)])
+    }
+
+    /// Returns true if the frame at `ip` is an allocator frame, see [`is_allocator_ip`].
+    ///
+    /// The first lookup of an address symbolizes it, the captures of other threads go on meanwhile.
+    pub(crate) fn is_allocator(&self, ip: usize) -> bool {
+        let key = (ip as u64) << 1;
+
+        for slot in self.probe(ip) {
+            match slot.load(Ordering::Relaxed) {
+                0 => break,
+                value if value & !1 == key => return value & 1 == 1,
+                _ => {}
+            }
+        }
+
+        let allocator = {
+            let _symbolizer = symbolize_lock();
+
+            is_allocator_ip(ip)
+        };
+
+        let value = key | allocator as u64;
+
+        for slot in self.probe(ip) {
+            match slot.compare_exchange(0, value, Ordering::Relaxed, Ordering::Relaxed) {
+                // cached, possibly by another thread.
+                Ok(_) => break,
+                Err(current) if current & !1 == key => break,
+                Err(_) => {}
+            }
+        }
+
+        allocator
+    }
+}
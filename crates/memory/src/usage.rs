@@ -0,0 +1,180 @@
+//! Heap usage counters of [`PprofAlloc`](crate::PprofAlloc), exact whatever the sampling.
+//!
+//! They are atomics updated on every allocation, reading them with [`stats`]
+//! costs nothing more than summing a few shards, unlike a [`snapshot`](crate::snapshot).
+//!
+//! Only the live bytes, checked against the budget on every allocation, are shared
+//! by all threads. The other counters are spread over shards, each thread updates
+//! its own shard, on its own cache line.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::helper::thread_index;
+
+/// Number of counter shards, threads share them round-robin.
+const SHARDS: usize = 64;
+
+/// An atomic alone on its cache line, 128 bytes as the prefetcher fetches line pairs.
+#[repr(align(128))]
+struct Padded(AtomicUsize);
+
+/// The counters of the threads sharing a shard, on its own cache line.
+///
+/// Blocks may be freed by another thread than the one allocating them, the `live_blocks`
+/// of a shard wraps around, only the sum over the shards is meaningful.
+#[repr(align(128))]
+struct Shard {
+    live_blocks: AtomicUsize,
+    allocations: AtomicUsize,
+    frees: AtomicUsize,
+    reallocations: AtomicUsize,
+    allocated_bytes: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SHARD: Shard = Shard {
+    live_blocks: AtomicUsize::new(0),
+    allocations: AtomicUsize::new(0),
+    frees: AtomicUsize::new(0),
+    reallocations: AtomicUsize::new(0),
+    allocated_bytes: AtomicUsize::new(0),
+};
+
+static SHARD_COUNTERS: [Shard; SHARDS] = [EMPTY_SHARD; SHARDS];
+
+/// Bytes currently allocated through [`PprofAlloc`](crate::PprofAlloc).
+static LIVE_BYTES: Padded = Padded(AtomicUsize::new(0));
+static PEAK_BYTES: Padded = Padded(AtomicUsize::new(0));
+
+/// Returns the counters shard of the calling thread.
+#[inline]
+fn shard() -> &'static Shard {
+    &SHARD_COUNTERS[thread_index() & (SHARDS - 1)]
+}
+
+/// Returns the sum of `counter` over the shards.
+fn sum(counter: impl Fn(&Shard) -> &AtomicUsize) -> usize {
+    SHARD_COUNTERS.iter().fold(0, |sum, shard| {
+        sum.wrapping_add(counter(shard).load(Ordering::Relaxed))
+    })
+}
+
+/// Statistics of the heap served by [`PprofAlloc`](crate::PprofAlloc), see [`stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Bytes currently allocated.
+    pub live_bytes: usize,
+    /// Number of blocks currently allocated.
+    pub live_blocks: usize,
+    /// The highest value of `live_bytes` since the start of the program.
+    pub peak_bytes: usize,
+    /// Number of allocations since the start of the program.
+    pub allocations: usize,
+    /// Number of frees since the start of the program.
+    pub frees: usize,
+    /// Number of reallocations since the start of the program.
+    pub reallocations: usize,
+    /// Bytes allocated since the start of the program, including the growth of reallocations.
+    pub allocated_bytes: usize,
+}
+
+/// Returns the heap statistics, counting every allocation of every thread,
+/// whatever the sampling, filters and recording state of the profiler.
+///
+/// The counters are summed without a lock, they may be slightly
+/// inconsistent with each other while other threads allocate.
+///
+/// ```no_run
+/// use hala_pprof_memory::{stats, PprofAlloc};
+///
+/// #[global_allocator]
+/// static ALLOC: PprofAlloc = PprofAlloc::new(10).with_sample_rate(512 * 1024);
+///
+/// fn main() {
+///     let stats = stats();
+///
+///     println!("heap: {} bytes in {} blocks", stats.live_bytes, stats.live_blocks);
+/// }
+/// ```
+pub fn stats() -> HeapStats {
+    HeapStats {
+        live_bytes: LIVE_BYTES.0.load(Ordering::Relaxed),
+        live_blocks: sum(|shard| &shard.live_blocks),
+        peak_bytes: PEAK_BYTES.0.load(Ordering::Relaxed),
+        allocations: sum(|shard| &shard.allocations),
+        frees: sum(|shard| &shard.frees),
+        reallocations: sum(|shard| &shard.reallocations),
+        allocated_bytes: sum(|shard| &shard.allocated_bytes),
+    }
+}
+
+/// Returns the bytes currently allocated through [`PprofAlloc`](crate::PprofAlloc).
+pub fn live_bytes() -> usize {
+    LIVE_BYTES.0.load(Ordering::Relaxed)
+}
+
+/// Records an allocation of `size` bytes, returns the new live bytes.
+#[inline]
+pub(crate) fn on_alloc(size: usize) -> usize {
+    let shard = shard();
+
+    shard.allocations.fetch_add(1, Ordering::Relaxed);
+    shard.live_blocks.fetch_add(1, Ordering::Relaxed);
+    shard.allocated_bytes.fetch_add(size, Ordering::Relaxed);
+
+    grow(size)
+}
+
+/// Records a free of `size` bytes, returns the new live bytes.
+#[inline]
+pub(crate) fn on_free(size: usize) -> usize {
+    let shard = shard();
+
+    shard.frees.fetch_add(1, Ordering::Relaxed);
+    shard.live_blocks.fetch_sub(1, Ordering::Relaxed);
+
+    shrink(size)
+}
+
+/// Records the reallocation of a block from `old_size` to `new_size` bytes,
+/// returns the new live bytes, `Ok` if the block grew.
+#[inline]
+pub(crate) fn on_realloc(old_size: usize, new_size: usize) -> Result<usize, usize> {
+    let shard = shard();
+
+    shard.reallocations.fetch_add(1, Ordering::Relaxed);
+
+    if new_size >= old_size {
+        shard
+            .allocated_bytes
+            .fetch_add(new_size - old_size, Ordering::Relaxed);
+
+        Ok(grow(new_size - old_size))
+    } else {
+        Err(shrink(old_size - new_size))
+    }
+}
+
+#[inline]
+fn grow(size: usize) -> usize {
+    let live = LIVE_BYTES
+        .0
+        .fetch_add(size, Ordering::Relaxed)
+        .wrapping_add(size);
+
+    // a load first, the peak is rarely raised.
+    if live > PEAK_BYTES.0.load(Ordering::Relaxed) {
+        PEAK_BYTES.0.fetch_max(live, Ordering::Relaxed);
+    }
+
+    live
+}
+
+#[inline]
+fn shrink(size: usize) -> usize {
+    // wrapping, an invalid free must not panic inside the allocator.
+    LIVE_BYTES
+        .0
+        .fetch_sub(size, Ordering::Relaxed)
+        .wrapping_sub(size)
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::hint::black_box;
+
+use hala_pprof_memory::{
+    proto::gperf::{Label, Sample},
+    report_mode, set_report_mode, snapshot_profile, PprofAlloc, Profile, ReportMode,
+};
+
+use common::calls;
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+#[inline(never)]
+fn cached() -> Vec<u8> {
+    black_box(vec![0u8; 1024])
+}
+
+#[inline(never)]
+fn fresh() -> Vec<u8> {
+    black_box(vec![0u8; 512])
+}
+
+/// Returns the live sample of the blocks allocated by `name`, and its `key` label.
+fn live<'a>(profile: &'a Profile, name: &str, key: &str) -> (&'a Sample, &'a Label) {
+    let sample = profile
+        .sample
+        .iter()
+        .find(|sample| sample.value[2] > 0 && calls(profile, sample, name))
+        .expect("live sample");
+
+    let label = sample
+        .label
+        .iter()
+        .find(|label| profile.string_table[label.key as usize] == key)
+        .expect("label");
+
+    (sample, label)
+}
+
+#[test]
+fn age_buckets() {
+    let cache = cached();
+
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+
+    let recent = fresh();
+
+    assert_eq!(report_mode(), ReportMode::Blocks);
+
+    let profile = snapshot_profile();
+
+    let (_, age) = live(&profile, "age_test::cached", "age");
+
+    assert!(age.num >= 1, "{} seconds", age.num);
+
+    set_report_mode(ReportMode::AgeBuckets);
+
+    assert_eq!(report_mode(), ReportMode::AgeBuckets);
+
+    let profile = snapshot_profile();
+
+    for (name, size, bucket) in [
+        ("age_test::cached", 1024, "1s-10s"),
+        ("age_test::fresh", 512, "<1s"),
+    ] {
+        let (sample, label) = live(&profile, name, "age_bucket");
+
+        assert_eq!(sample.value[2..4], [1, size]);
+        assert_eq!(profile.string_table[label.str as usize], bucket);
+    }
+
+    drop((cache, recent));
+}
@@ -0,0 +1,44 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fs,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use hala_pprof_memory::{set_snapshot_config, snapshot, PprofAlloc, SnapshotConfig};
+
+static INNER_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        INNER_ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOC: PprofAlloc<CountingAlloc> = PprofAlloc::from_allocator(CountingAlloc, 10);
+
+#[test]
+fn inner_allocator() {
+    let directory = std::env::temp_dir().join(format!("pprof-allocator-{}", std::process::id()));
+
+    set_snapshot_config(SnapshotConfig::new().with_directory(&directory));
+
+    let before = INNER_ALLOCS.load(Ordering::Relaxed);
+
+    let buf = std::hint::black_box(vec![0u8; 1024]);
+
+    assert!(INNER_ALLOCS.load(Ordering::Relaxed) > before);
+
+    drop(buf);
+
+    snapshot().unwrap();
+
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,56 @@
+use std::{
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use hala_pprof_memory::{
+    clear_memory_budget, live_bytes, set_memory_budget, MemoryBudget, PprofAlloc,
+};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+static FIRED: AtomicUsize = AtomicUsize::new(0);
+
+fn over_budget(live: usize) {
+    assert!(live > 0);
+    FIRED.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test]
+fn budget() {
+    let base = live_bytes();
+
+    set_memory_budget(
+        MemoryBudget::new(base + (8 << 20))
+            .with_hysteresis(4 << 20)
+            .with_callback(over_budget),
+    );
+
+    let spike = black_box(vec![0u8; 16 << 20]);
+
+    assert!(live_bytes() >= base + (16 << 20));
+    assert_eq!(FIRED.load(Ordering::Relaxed), 1);
+
+    // still above the limit, no new crossing.
+    let more = black_box(vec![0u8; 1 << 20]);
+
+    assert_eq!(FIRED.load(Ordering::Relaxed), 1);
+
+    drop((spike, more));
+
+    // dropped below the hysteresis, fires again.
+    let spike = black_box(vec![0u8; 16 << 20]);
+
+    assert_eq!(FIRED.load(Ordering::Relaxed), 2);
+
+    clear_memory_budget();
+
+    drop(spike);
+
+    let spike = black_box(vec![0u8; 16 << 20]);
+
+    assert_eq!(FIRED.load(Ordering::Relaxed), 2);
+
+    drop(spike);
+}
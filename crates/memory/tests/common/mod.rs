@@ -0,0 +1,14 @@
+//! Helpers shared by the integration tests.
+
+use hala_pprof_memory::{proto::gperf::Sample, Profile};
+
+/// Returns true if the stack of `sample` has a function whose name contains `name`.
+pub fn calls(profile: &Profile, sample: &Sample, name: &str) -> bool {
+    sample.location_id.iter().any(|id| {
+        profile.location[*id as usize - 1].line.iter().any(|line| {
+            let function = &profile.function[line.function_id as usize - 1];
+
+            profile.string_table[function.system_name as usize].contains(name)
+        })
+    })
+}
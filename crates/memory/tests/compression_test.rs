@@ -0,0 +1,55 @@
+use std::fs;
+
+use hala_pprof_memory::{
+    read_profile, set_snapshot_config, snapshot, snapshot_bytes, PprofAlloc, SnapshotConfig,
+};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+#[test]
+fn gzip_by_default() {
+    let directory = std::env::temp_dir().join(format!("pprof-gzip-{}", std::process::id()));
+
+    let buf = vec![0u8; 1 << 20];
+
+    let compressed = snapshot_bytes();
+
+    assert_eq!(compressed[..2], [0x1f, 0x8b]);
+    assert!(!read_profile(compressed.as_slice())
+        .unwrap()
+        .sample
+        .is_empty());
+
+    set_snapshot_config(SnapshotConfig::new().with_directory(&directory));
+
+    let path = snapshot().unwrap();
+
+    assert!(path.to_string_lossy().ends_with(".pprof.pb.gz"));
+    assert_eq!(fs::read(&path).unwrap()[..2], [0x1f, 0x8b]);
+
+    set_snapshot_config(
+        SnapshotConfig::new()
+            .with_directory(&directory)
+            .with_compression(false),
+    );
+
+    let raw = snapshot_bytes();
+
+    assert_ne!(raw[..2], [0x1f, 0x8b]);
+    assert!(!read_profile(raw.as_slice()).unwrap().sample.is_empty());
+
+    let path = snapshot().unwrap();
+
+    assert!(path.to_string_lossy().ends_with(".pprof.pb"));
+    assert!(!read_profile(fs::File::open(&path).unwrap())
+        .unwrap()
+        .sample
+        .is_empty());
+
+    assert!(read_profile(&b"not a profile"[..]).is_err());
+
+    fs::remove_dir_all(&directory).unwrap();
+
+    drop(buf);
+}
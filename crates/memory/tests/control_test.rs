@@ -0,0 +1,73 @@
+use std::{fs, hint::black_box};
+
+use hala_pprof_memory::{
+    is_running, pause, reset, resume, set_snapshot_config, snapshot, snapshot_profile, start, stop,
+    PprofAlloc, SnapshotConfig,
+};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10).with_autostart(false);
+
+/// Returns the sizes of the live blocks, each one has its own sample.
+fn live_sizes() -> Vec<i64> {
+    snapshot_profile()
+        .sample
+        .iter()
+        .filter(|sample| sample.value[2] > 0)
+        .map(|sample| sample.value[3])
+        .collect()
+}
+
+#[test]
+fn start_stop() {
+    let directory = std::env::temp_dir().join(format!("pprof-control-{}", std::process::id()));
+
+    set_snapshot_config(SnapshotConfig::new().with_directory(&directory));
+
+    let before = black_box(vec![0u8; 1001]);
+
+    assert!(!is_running());
+
+    start();
+
+    assert!(is_running());
+
+    let during = black_box(vec![0u8; 2002]);
+
+    pause();
+
+    assert!(!is_running());
+
+    let paused = black_box(vec![0u8; 3003]);
+
+    resume();
+
+    assert!(is_running());
+
+    stop();
+
+    assert!(!is_running());
+
+    let stopped = black_box(vec![0u8; 4004]);
+
+    let sizes = live_sizes();
+
+    assert!(sizes.contains(&2002));
+
+    for excluded in [1001, 3003, 4004] {
+        assert!(!sizes.contains(&excluded), "{} bytes recorded", excluded);
+    }
+
+    // freed after stop, still matched.
+    drop(during);
+
+    assert!(!live_sizes().contains(&2002));
+
+    drop((before, paused, stopped));
+
+    snapshot().unwrap();
+
+    reset();
+
+    fs::remove_dir_all(&directory).unwrap();
+}
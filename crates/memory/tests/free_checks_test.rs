@@ -0,0 +1,98 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+
+use hala_pprof_memory::{memory_errors, MemoryErrorKind, PprofAlloc};
+
+#[global_allocator]
+static ALLOC: PprofAlloc<System> = PprofAlloc::from_allocator(System, 32).with_free_checks(true);
+
+#[test]
+fn free_checks() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    let (double_free, mismatch, freed, resized) = unsafe {
+        let double_free = ALLOC.alloc(layout);
+
+        ALLOC.dealloc(double_free, layout);
+        ALLOC.dealloc(double_free, layout);
+
+        let mismatch = ALLOC.alloc(layout);
+
+        ALLOC.dealloc(mismatch, Layout::from_size_align(32, 8).unwrap());
+
+        // rejected, the inner allocator would free the quarantined block.
+        let freed = ALLOC.alloc(layout);
+
+        ALLOC.dealloc(freed, layout);
+
+        assert!(ALLOC.realloc(freed, layout, 128).is_null());
+
+        // rejected, the block is left untouched and freed normally.
+        let resized = ALLOC.alloc(layout);
+
+        assert!(ALLOC
+            .realloc(resized, Layout::from_size_align(32, 8).unwrap(), 128)
+            .is_null());
+
+        ALLOC.dealloc(resized, layout);
+
+        (
+            double_free as usize,
+            mismatch as usize,
+            freed as usize,
+            resized as usize,
+        )
+    };
+
+    let mut unknown = [0u8; 16];
+
+    unsafe { ALLOC.dealloc(unknown.as_mut_ptr(), Layout::new::<[u8; 16]>()) };
+
+    // valid frees go through the quarantine, the evicted blocks are freed.
+    for _ in 0..10_000 {
+        drop(std::hint::black_box(vec![0u8; 64]));
+    }
+
+    let errors = memory_errors();
+
+    assert_eq!(errors.len(), 5, "{:#?}", errors);
+
+    let find = |address: usize| {
+        errors
+            .iter()
+            .find(|error| error.address == address)
+            .unwrap_or_else(|| panic!("no error at 0x{:x}", address))
+    };
+
+    let error = find(double_free);
+    assert_eq!(error.kind, MemoryErrorKind::DoubleFree);
+    assert!(!error.free_backtrace.is_empty());
+    assert!(!error.previous_free_backtrace.is_empty());
+    assert!(!error.alloc_backtrace.is_empty());
+
+    let error = find(mismatch);
+    assert_eq!(error.kind, MemoryErrorKind::LayoutMismatch);
+    assert_eq!(error.recorded_layout, Some(layout));
+    assert_eq!(error.layout.size(), 32);
+
+    let error = find(freed);
+    assert_eq!(error.kind, MemoryErrorKind::DoubleFree);
+    assert!(!error.previous_free_backtrace.is_empty());
+
+    let error = find(resized);
+    assert_eq!(error.kind, MemoryErrorKind::LayoutMismatch);
+    assert_eq!(error.recorded_layout, Some(layout));
+
+    let error = find(unknown.as_ptr() as usize);
+    assert_eq!(error.kind, MemoryErrorKind::UnknownFree);
+
+    assert!(find(double_free)
+        .to_string()
+        .contains("previously freed at"));
+
+    // the errors returned above are allocated by the profiler, freeing them is fine.
+    let count = errors.len();
+
+    drop(errors);
+
+    assert_eq!(memory_errors().len(), count);
+}
@@ -0,0 +1,83 @@
+use std::hint::black_box;
+
+use hala_pprof_memory::{snapshot_profile, PprofAlloc};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(32);
+
+/// Inlined into `outer` by every profile, unlike the standard library allocation path.
+#[inline(always)]
+fn inner() -> Vec<u8> {
+    black_box(vec![0u8; 1 << 20])
+}
+
+#[inline(never)]
+fn outer() -> Vec<u8> {
+    inner()
+}
+
+#[test]
+fn inlined_frames_are_lines() {
+    let buf = outer();
+
+    let profile = snapshot_profile();
+
+    let names = |location: &hala_pprof_memory::proto::gperf::Location| {
+        location
+            .line
+            .iter()
+            .map(|line| {
+                let function = &profile.function[line.function_id as usize - 1];
+
+                assert_eq!(function.id, line.function_id);
+
+                profile.string_table[function.system_name as usize].clone()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // without debug info, e.g. in release builds, the inlined functions cannot be recovered.
+    let debug_info = profile
+        .function
+        .iter()
+        .any(|function| !profile.string_table[function.filename as usize].is_empty());
+
+    if debug_info {
+        let inlined = profile
+            .location
+            .iter()
+            .map(names)
+            .find(|names| {
+                names.iter().any(|name| name.contains("inline_test::inner"))
+                    && names.iter().any(|name| name.contains("inline_test::outer"))
+            })
+            .expect("inlined allocation path");
+
+        // leaf first.
+        let leaf = inlined
+            .iter()
+            .position(|name| name.contains("inline_test::inner"))
+            .unwrap();
+
+        let caller = inlined
+            .iter()
+            .position(|name| name.contains("inline_test::outer"))
+            .unwrap();
+
+        assert!(leaf < caller);
+    }
+
+    // one location per instruction pointer.
+    let mut addresses = profile
+        .location
+        .iter()
+        .map(|location| location.address)
+        .collect::<Vec<_>>();
+
+    addresses.sort();
+    addresses.dedup();
+
+    assert_eq!(addresses.len(), profile.location.len());
+
+    drop(buf);
+}
@@ -0,0 +1,64 @@
+use std::hint::black_box;
+
+use hala_pprof_memory::{
+    proto::gperf::Sample, set_labels, snapshot_profile, with_labels, PprofAlloc, Profile,
+};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+fn label<'a>(profile: &'a Profile, sample: &Sample, key: &str) -> Option<&'a str> {
+    sample
+        .label
+        .iter()
+        .find(|label| profile.string_table[label.key as usize] == key)
+        .map(|label| profile.string_table[label.str as usize].as_str())
+}
+
+fn live_sample(profile: &Profile, size: i64) -> &Sample {
+    profile
+        .sample
+        .iter()
+        .find(|sample| sample.value[2] > 0 && sample.value[3] == size)
+        .expect("live block sample")
+}
+
+#[test]
+fn scoped_labels() {
+    let search = with_labels(&[("endpoint", "/search")], || {
+        let guard = set_labels(&[("stage", "parse"), ("bad\0key", "value"), ("key", "bad\0")]);
+
+        let parsed = black_box(vec![0u8; 1281]);
+
+        drop(guard);
+
+        (parsed, black_box(vec![0u8; 2562]))
+    });
+
+    let unlabeled = black_box(vec![0u8; 3843]);
+
+    let profile = snapshot_profile();
+
+    let parsed = live_sample(&profile, 1281);
+
+    assert_eq!(label(&profile, parsed, "endpoint"), Some("/search"));
+    assert_eq!(label(&profile, parsed, "stage"), Some("parse"));
+
+    // labels with a '\0' are ignored.
+    assert!(parsed.label.iter().all(|label| {
+        !profile.string_table[label.key as usize].contains('\0')
+            && !profile.string_table[label.str as usize].contains('\0')
+    }));
+    assert_eq!(label(&profile, parsed, "key"), None);
+
+    let rest = live_sample(&profile, 2562);
+
+    assert_eq!(label(&profile, rest, "endpoint"), Some("/search"));
+    assert_eq!(label(&profile, rest, "stage"), None);
+
+    let outside = live_sample(&profile, 3843);
+
+    assert_eq!(label(&profile, outside, "endpoint"), None);
+
+    drop((search, unlabeled));
+}
@@ -0,0 +1,84 @@
+use std::{fs, hint::black_box, process::Command};
+
+use hala_pprof_memory::{
+    leak, mark_leaked, read_profile, set_snapshot_config, PprofAlloc, SnapshotConfig,
+};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10).with_leak_report(true);
+
+/// Set in the child process, the directory of its leak report.
+const CHILD: &str = "PPROF_LEAK_TEST_DIRECTORY";
+
+/// Leaks three blocks, two of them on purpose, the report is written by the exit hook.
+fn leak_blocks(directory: &str) {
+    set_snapshot_config(SnapshotConfig::new().with_directory(directory));
+
+    // reported by the exit hook.
+    std::mem::forget(black_box(vec![0u8; 1024]));
+
+    // leaked on purpose, excluded from the report.
+    let config = leak(black_box(Box::new([0u8; 777])));
+
+    assert_eq!(config.len(), 777);
+
+    let table = black_box(Vec::<u64>::with_capacity(100)).leak();
+
+    assert!(mark_leaked(table.as_ptr()));
+}
+
+#[test]
+fn leaks() {
+    if let Ok(directory) = std::env::var(CHILD) {
+        leak_blocks(&directory);
+        return;
+    }
+
+    // this process reports its own leaks too, after the test: every run overwrites the same file.
+    set_snapshot_config(
+        SnapshotConfig::new()
+            .with_directory(std::env::temp_dir().join("pprof-leak"))
+            .with_file_name("memory.leaks.pprof.pb.gz"),
+    );
+
+    let directory = std::env::temp_dir().join(format!("pprof-leak-{}", std::process::id()));
+
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["leaks", "--exact", "--test-threads", "1"])
+        .env(CHILD, &directory)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(stderr.contains("leaked blocks"), "{}", stderr);
+
+    let report = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| {
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("memory.leaks")
+        })
+        .expect("leak report");
+
+    let profile = read_profile(fs::File::open(report).unwrap()).unwrap();
+
+    let leaked = profile
+        .sample
+        .iter()
+        .map(|sample| sample.value[3])
+        .collect::<Vec<_>>();
+
+    assert!(leaked.contains(&1024), "{:?}", leaked);
+
+    for excluded in [777, 800] {
+        assert!(!leaked.contains(&excluded), "{:?}", leaked);
+    }
+
+    fs::remove_dir_all(&directory).unwrap();
+}
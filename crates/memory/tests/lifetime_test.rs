@@ -0,0 +1,103 @@
+mod common;
+
+use std::{
+    fs,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use hala_pprof_memory::{
+    proto::gperf::Sample, read_profile, set_snapshot_config, short_lived_snapshot, PprofAlloc,
+    Profile, SnapshotConfig,
+};
+
+use common::calls;
+
+#[global_allocator]
+static ALLOC: PprofAlloc =
+    PprofAlloc::new(10).with_short_lived_threshold(Duration::from_millis(100));
+
+/// Allocates 1000 blocks freed right away.
+#[inline(never)]
+fn churn() {
+    for i in 0..1000 {
+        drop(black_box(vec![i as u8; 256]));
+    }
+}
+
+#[inline(never)]
+fn keep() -> Vec<u8> {
+    black_box(vec![0u8; 256])
+}
+
+/// Returns the `lifetime` lower bound, in microseconds, of a histogram sample.
+fn lifetime(profile: &Profile, sample: &Sample) -> Option<i64> {
+    sample
+        .label
+        .iter()
+        .find(|label| profile.string_table[label.key as usize] == "lifetime")
+        .map(|label| label.num)
+}
+
+#[test]
+fn short_lived() {
+    let directory = std::env::temp_dir().join(format!("pprof-lifetime-{}", std::process::id()));
+
+    set_snapshot_config(SnapshotConfig::new().with_directory(&directory));
+
+    churn();
+
+    let started = Instant::now();
+
+    let kept = keep();
+
+    std::thread::sleep(Duration::from_millis(200));
+
+    drop(kept);
+
+    let elapsed = started.elapsed().as_micros() as i64;
+
+    let profile = read_profile(fs::File::open(short_lived_snapshot().unwrap()).unwrap()).unwrap();
+
+    // the short-lived totals carry no lifetime label, the histogram samples do.
+    let (short_lived, histogram): (Vec<_>, Vec<_>) = profile
+        .sample
+        .iter()
+        .filter(|sample| calls(&profile, sample, "lifetime_test::churn"))
+        .partition(|sample| lifetime(&profile, sample).is_none());
+
+    // an unrolled loop allocates from several call sites.
+    let totals = short_lived.iter().fold([0, 0], |[objects, space], sample| {
+        [objects + sample.value[0], space + sample.value[1]]
+    });
+
+    assert_eq!(totals, [1000, 256 * 1000]);
+
+    assert_eq!(
+        histogram.iter().map(|sample| sample.value[2]).sum::<i64>(),
+        1000
+    );
+    assert!(histogram
+        .iter()
+        .all(|sample| lifetime(&profile, sample).unwrap() < 100_000));
+
+    // freed after 200ms: in the bucket holding its lifetime, not short-lived.
+    let kept = profile
+        .sample
+        .iter()
+        .filter(|sample| calls(&profile, sample, "lifetime_test::keep"))
+        .collect::<Vec<_>>();
+
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0].value, [0, 0, 1]);
+
+    let lower = lifetime(&profile, kept[0]).unwrap();
+
+    assert!(
+        lower * 2 > 200_000 && lower <= elapsed,
+        "{}us bucket",
+        lower
+    );
+
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,51 @@
+use hala_pprof_memory::{snapshot_profile, PprofAlloc};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+#[cfg(target_os = "linux")]
+#[test]
+fn locations_are_mapped() {
+    let buf = vec![0u8; 1 << 20];
+
+    let profile = snapshot_profile();
+
+    assert!(!profile.mapping.is_empty());
+
+    let exe = std::env::current_exe().unwrap();
+
+    let main = profile
+        .mapping
+        .iter()
+        .find(|mapping| profile.string_table[mapping.filename as usize] == exe.to_string_lossy())
+        .expect("mapping of the test binary");
+
+    assert!(main.memory_start < main.memory_limit);
+    assert!(main.has_functions);
+
+    // libc is shipped with a build id by every distribution.
+    assert!(profile
+        .mapping
+        .iter()
+        .any(|mapping| !profile.string_table[mapping.build_id as usize].is_empty()));
+
+    for location in &profile.location {
+        if location.mapping_id == 0 {
+            continue;
+        }
+
+        let mapping = &profile.mapping[location.mapping_id as usize - 1];
+
+        assert_eq!(mapping.id, location.mapping_id);
+        assert!(
+            mapping.memory_start <= location.address && location.address < mapping.memory_limit
+        );
+    }
+
+    assert!(profile
+        .location
+        .iter()
+        .any(|location| location.mapping_id == main.id));
+
+    drop(buf);
+}
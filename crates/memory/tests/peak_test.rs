@@ -0,0 +1,76 @@
+mod common;
+
+use std::{fs, hint::black_box};
+
+use hala_pprof_memory::{
+    peak_snapshot, read_profile, set_snapshot_config, snapshot_profile, PprofAlloc, SnapshotConfig,
+};
+
+use common::calls;
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10).with_peak_margin(64 * 1024);
+
+const BLOCKS: usize = 16;
+
+const SIZE: usize = 64 * 1024;
+
+#[inline(never)]
+fn spike() -> Vec<Vec<u8>> {
+    let mut blocks = Vec::with_capacity(BLOCKS);
+
+    for _ in 0..BLOCKS {
+        blocks.push(black_box(vec![0u8; SIZE]));
+    }
+
+    blocks
+}
+
+#[test]
+fn peak() {
+    let directory = std::env::temp_dir().join(format!("pprof-peak-{}", std::process::id()));
+
+    set_snapshot_config(SnapshotConfig::new().with_directory(&directory));
+
+    drop(black_box(spike()));
+
+    // the spike is gone from the live heap, only the peak profile shows it.
+    let live = snapshot_profile();
+
+    assert!(!live
+        .sample
+        .iter()
+        .any(|sample| sample.value[2] > 0 && calls(&live, sample, "peak_test::spike")));
+
+    let peak = read_profile(fs::File::open(peak_snapshot().unwrap()).unwrap()).unwrap();
+
+    let spiked = peak
+        .sample
+        .iter()
+        .filter(|sample| calls(&peak, sample, "peak_test::spike"))
+        .map(|sample| [sample.value[2], sample.value[3]])
+        .collect::<Vec<_>>();
+
+    assert!(
+        spiked.contains(&[BLOCKS as i64, (BLOCKS * SIZE) as i64]),
+        "{:?}",
+        spiked
+    );
+
+    let highest = peak
+        .comment
+        .iter()
+        .map(|id| &peak.string_table[*id as usize])
+        .find(|comment| comment.starts_with("peak: "))
+        .expect("peak comment");
+
+    let bytes = |field: &str| -> usize {
+        let (_, rest) = highest.split_once(field).unwrap();
+        rest.split(' ').next().unwrap().parse().unwrap()
+    };
+
+    assert!(bytes("peak: ") >= BLOCKS * SIZE, "{}", highest);
+    assert!(bytes("highest ") >= bytes("peak: "), "{}", highest);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
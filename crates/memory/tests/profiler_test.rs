@@ -1,7 +1,25 @@
-use hala_pprof_memory::{snapshot, PprofAlloc};
+mod common;
+
+use std::fs;
+
+use hala_pprof_memory::{
+    profiler_overhead, set_snapshot_config, snapshot, snapshot_profile, thread_stats, PprofAlloc,
+    Profile, SnapshotConfig,
+};
+
+use common::calls;
 
 #[global_allocator]
-static ALLOC: PprofAlloc = PprofAlloc(10);
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+/// Writes a snapshot out of the crate directory and removes it.
+fn write_snapshot() {
+    set_snapshot_config(SnapshotConfig::new().with_directory(
+        std::env::temp_dir().join(format!("pprof-profiler-{}", std::process::id())),
+    ));
+
+    fs::remove_file(snapshot().unwrap()).unwrap();
+}
 
 #[test]
 fn alloc_string() {
@@ -9,9 +27,124 @@ fn alloc_string() {
         _ = format!("hello world {}", "===");
 
         if i == 50 {
-            snapshot();
+            write_snapshot();
         }
     }
 
-    snapshot();
+    write_snapshot();
+}
+
+#[inline(never)]
+fn grow() -> Vec<u64> {
+    let mut buf = Vec::new();
+
+    for i in 0..1000 {
+        buf.push(i);
+    }
+
+    buf
+}
+
+#[inline(never)]
+fn zeroed() -> Vec<u8> {
+    // not a tail call, the frame stays on the stack.
+    std::hint::black_box(vec![0u8; 4096])
+}
+
+/// Returns the sum of the values of the samples whose stack calls `name`.
+fn totals(profile: &Profile, name: &str) -> Vec<i64> {
+    profile
+        .sample
+        .iter()
+        .filter(|sample| calls(profile, sample, name))
+        .fold(vec![0; profile.sample_type.len()], |totals, sample| {
+            totals
+                .iter()
+                .zip(&sample.value)
+                .map(|(a, b)| a + b)
+                .collect()
+        })
+}
+
+#[test]
+fn realloc_vec() {
+    let buf = std::hint::black_box(grow());
+
+    let zeroed = zeroed();
+
+    let profile = snapshot_profile();
+
+    // alloc_objects, alloc_space, inuse_objects, inuse_space, realloc_objects.
+    let grown = totals(&profile, "profiler_test::grow");
+
+    assert_eq!(grown[0], 1);
+    assert_eq!(grown[2], 1);
+    assert_eq!(grown[3], (buf.capacity() * 8) as i64);
+    assert!(grown[4] > 0);
+
+    assert_eq!(
+        totals(&profile, "profiler_test::zeroed"),
+        [1, 4096, 1, 4096, 0]
+    );
+
+    drop((buf, zeroed));
+}
+
+/// Makes 1000 allocations and 999 reallocations.
+#[inline(never)]
+fn work() {
+    for i in 0..1000 {
+        let mut buf = vec![0u8; i];
+
+        buf.extend_from_slice(b"hello world");
+
+        std::hint::black_box(buf);
+    }
+}
+
+#[test]
+fn concurrent_alloc() {
+    let handles = (0..8)
+        .map(|index| {
+            std::thread::Builder::new()
+                .name(format!("worker-{}", index))
+                .spawn(work)
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    write_snapshot();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // alloc_objects, alloc_space, inuse_objects, inuse_space, realloc_objects.
+    let worked = totals(&snapshot_profile(), "profiler_test::work");
+
+    assert_eq!(worked[0], 8 * 1000);
+    assert_eq!(worked[2..], [0, 0, 8 * 999]);
+
+    let workers = thread_stats()
+        .into_iter()
+        .filter(|stats| stats.name.starts_with("worker-"))
+        .collect::<Vec<_>>();
+
+    assert_eq!(workers.len(), 8);
+
+    for stats in workers {
+        assert!(stats.allocations >= 1000, "{:?}", stats);
+    }
+}
+
+#[test]
+fn overhead() {
+    let buf = vec![0u8; 1024];
+
+    assert!(profiler_overhead() > 0);
+
+    // the metadata lives in whole mapped pages.
+    assert_eq!(profiler_overhead() % 4096, 0);
+
+    drop(buf);
 }
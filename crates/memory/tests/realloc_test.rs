@@ -0,0 +1,40 @@
+use std::hint::black_box;
+
+use hala_pprof_memory::{memory_errors, PprofAlloc};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(16).with_free_checks(true);
+
+/// Grows a vector from empty, every push past the capacity is a realloc.
+#[inline(never)]
+fn grow() {
+    let mut buf = vec![];
+
+    for i in 0..256u64 {
+        buf.push(black_box(i));
+    }
+
+    drop(black_box(buf));
+}
+
+#[test]
+fn concurrent_reallocs() {
+    let threads = (0..4)
+        .map(|_| {
+            std::thread::spawn(|| {
+                for _ in 0..1000 {
+                    grow();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // a freed address handed out again while its block is moved is never mistaken for it.
+    let errors = memory_errors();
+
+    assert!(errors.is_empty(), "{:#?}", errors);
+}
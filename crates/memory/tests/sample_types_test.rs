@@ -0,0 +1,67 @@
+mod common;
+
+use hala_pprof_memory::{snapshot_profile, PprofAlloc};
+
+use common::calls;
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+#[inline(never)]
+fn churn() {
+    for i in 0..100 {
+        std::hint::black_box(vec![i as u8; 1024]);
+    }
+}
+
+#[test]
+fn heap_sample_types() {
+    churn();
+
+    let profile = snapshot_profile();
+
+    let sample_types = profile
+        .sample_type
+        .iter()
+        .map(|sample_type| {
+            (
+                profile.string_table[sample_type.type_ as usize].as_str(),
+                profile.string_table[sample_type.unit as usize].as_str(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        sample_types,
+        [
+            ("alloc_objects", "count"),
+            ("alloc_space", "bytes"),
+            ("inuse_objects", "count"),
+            ("inuse_space", "bytes"),
+            ("realloc_objects", "count"),
+        ]
+    );
+
+    assert_eq!(
+        profile.string_table[profile.default_sample_type as usize],
+        "inuse_space"
+    );
+
+    // freed blocks are still counted as allocated.
+    let (allocated, live) = profile
+        .sample
+        .iter()
+        .filter(|sample| calls(&profile, sample, "sample_types_test::churn"))
+        .fold(([0; 2], [0; 2]), |(allocated, live), sample| {
+            (
+                [
+                    allocated[0] + sample.value[0],
+                    allocated[1] + sample.value[1],
+                ],
+                [live[0] + sample.value[2], live[1] + sample.value[3]],
+            )
+        });
+
+    assert_eq!(allocated, [100, 100 * 1024]);
+    assert_eq!(live, [0, 0]);
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use hala_pprof_memory::{snapshot_profile, PprofAlloc};
+
+use common::calls;
+
+const SAMPLE_RATE: usize = 4096;
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10).with_sample_rate(SAMPLE_RATE);
+
+const COUNT: usize = 10_000;
+
+const SIZE: usize = 1000;
+
+#[inline(never)]
+fn allocate(buffers: &mut Vec<Vec<u8>>) {
+    for _ in 0..COUNT {
+        buffers.push(std::hint::black_box(vec![0u8; SIZE]));
+    }
+}
+
+#[test]
+fn sampled_alloc() {
+    let mut buffers = Vec::with_capacity(COUNT);
+
+    allocate(&mut buffers);
+
+    let profile = snapshot_profile();
+
+    let (objects, bytes) = profile
+        .sample
+        .iter()
+        .filter(|sample| calls(&profile, sample, "sampling_test::allocate"))
+        .fold((0, 0), |(objects, bytes), sample| {
+            (objects + sample.value[2], bytes + sample.value[3])
+        });
+
+    // about 2400 allocations are recorded, the estimates are within a few percents.
+    let within = |estimate: i64, real: usize| {
+        let real = real as f64;
+
+        (estimate as f64 - real).abs() < real * 0.2
+    };
+
+    assert!(within(objects, COUNT), "{} objects", objects);
+    assert!(within(bytes, COUNT * SIZE), "{} bytes", bytes);
+
+    drop(buffers);
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use std::hint::black_box;
+
+use hala_pprof_memory::{snapshot_profile, PprofAlloc, Profile};
+
+use common::calls;
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10).with_size_range(1024, usize::MAX);
+
+/// Returns the values of the `[untracked]` sample, zeroes if there is none.
+fn untracked(profile: &Profile) -> [i64; 2] {
+    profile
+        .sample
+        .iter()
+        .find(|sample| calls(profile, sample, "[untracked]"))
+        .map_or([0, 0], |sample| [sample.value[0], sample.value[1]])
+}
+
+#[test]
+fn size_filter() {
+    let mut small = Vec::with_capacity(1000);
+
+    let before = untracked(&snapshot_profile());
+
+    // counted as untracked, without a stack capture.
+    for i in 0..1000u32 {
+        small.push(black_box(Box::new(i)));
+    }
+
+    let large = black_box(vec![0u8; 4096]);
+
+    let profile = snapshot_profile();
+
+    let after = untracked(&profile);
+
+    assert_eq!([after[0] - before[0], after[1] - before[1]], [1000, 4000]);
+
+    // the untracked blocks have no live sample, the large one has.
+    assert!(profile
+        .sample
+        .iter()
+        .all(|sample| sample.value[2] == 0 || sample.value[3] >= 1024));
+    assert!(profile
+        .sample
+        .iter()
+        .any(|sample| sample.value[2..4] == [1, 4096]));
+
+    drop((small, large));
+}
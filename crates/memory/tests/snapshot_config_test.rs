@@ -0,0 +1,61 @@
+use std::fs;
+
+use hala_pprof_memory::{set_snapshot_config, snapshot, PprofAlloc, SnapshotConfig};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+#[test]
+fn snapshot_naming_and_retention() {
+    let directory = std::env::temp_dir().join(format!("pprof-memory-{}", std::process::id()));
+
+    let config = SnapshotConfig::new()
+        .with_directory(&directory)
+        .with_file_name("{hostname}-{pid}-{prefix}-{seq}.pb");
+
+    set_snapshot_config(config.clone().with_max_files(3));
+
+    // not a profile, never removed.
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("notes.txt"), "keep").unwrap();
+
+    // a profile of another process, never removed.
+    fs::write(directory.join("host-1-memory-0.pb"), "keep").unwrap();
+
+    let mut paths = vec![];
+
+    for _ in 0..5 {
+        paths.push(snapshot().unwrap());
+    }
+
+    let file_name = paths[4].file_name().unwrap().to_string_lossy().into_owned();
+
+    assert!(file_name.ends_with(&format!("-{}-memory-4.pb", std::process::id())));
+
+    let mut names = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+
+    names.sort();
+
+    assert_eq!(names.len(), 5);
+    assert!(names.contains(&"notes.txt".to_owned()));
+    assert!(names.contains(&"host-1-memory-0.pb".to_owned()));
+    assert!(paths[2..].iter().all(|path| path.exists()));
+
+    // smaller than one profile, only the newest is kept.
+    set_snapshot_config(config.with_max_bytes(1));
+
+    let newest = snapshot().unwrap();
+
+    let names = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+
+    assert_eq!(names.len(), 3);
+    assert!(newest.exists());
+
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,57 @@
+use std::hint::black_box;
+
+use hala_pprof_memory::{
+    parse_profile, snapshot_bytes, snapshot_profile, snapshot_to_writer, PprofAlloc, Profile,
+};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+fn sample_types(profile: &Profile) -> Vec<&str> {
+    profile
+        .sample_type
+        .iter()
+        .map(|sample_type| profile.string_table[sample_type.type_ as usize].as_str())
+        .collect()
+}
+
+#[test]
+fn in_memory_snapshots() {
+    let buf = black_box(vec![0u8; 1 << 20]);
+
+    let profile = snapshot_profile();
+
+    assert_eq!(
+        sample_types(&profile),
+        [
+            "alloc_objects",
+            "alloc_space",
+            "inuse_objects",
+            "inuse_space",
+            "realloc_objects"
+        ]
+    );
+
+    assert!(profile
+        .sample
+        .iter()
+        .any(|sample| sample.value[3] >= 1 << 20));
+
+    let decoded = parse_profile(&snapshot_bytes()).unwrap();
+
+    assert_eq!(sample_types(&decoded), sample_types(&profile));
+    assert!(!decoded.sample.is_empty());
+
+    let mut written = vec![];
+
+    snapshot_to_writer(&mut written).unwrap();
+
+    let decoded = parse_profile(&written).unwrap();
+
+    assert!(decoded
+        .sample
+        .iter()
+        .any(|sample| sample.value[3] >= 1 << 20));
+
+    drop(buf);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use hala_pprof_memory::{snapshot_profile, PprofAlloc};
+
+use common::calls;
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+#[inline(never)]
+fn allocate() -> Vec<Vec<u8>> {
+    let mut blocks = Vec::with_capacity(100);
+
+    for _ in 0..100 {
+        blocks.push(std::hint::black_box(vec![0u8; 64]));
+    }
+
+    blocks
+}
+
+#[test]
+fn interned_stacks() {
+    let blocks = allocate();
+
+    let profile = snapshot_profile();
+
+    let samples = profile
+        .sample
+        .iter()
+        .filter(|sample| calls(&profile, sample, "stacks_test::allocate"))
+        .collect::<Vec<_>>();
+
+    // one sample per live block, all of them on the same stack.
+    let live = samples
+        .iter()
+        .filter(|sample| sample.value[2] > 0 && sample.value[3] == 64)
+        .collect::<Vec<_>>();
+
+    assert_eq!(live.len(), 100);
+    assert!(live
+        .iter()
+        .all(|sample| sample.location_id == live[0].location_id));
+
+    // and one cumulative sample for the stack they share.
+    let allocated = samples
+        .iter()
+        .filter(|sample| sample.location_id == live[0].location_id && sample.value[0] > 0)
+        .collect::<Vec<_>>();
+
+    assert_eq!(allocated.len(), 1);
+    assert_eq!(allocated[0].value[..2], [100, 100 * 64]);
+
+    drop(blocks);
+}
@@ -0,0 +1,29 @@
+use hala_pprof_memory::{stats, PprofAlloc};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10).with_sample_rate(512 * 1024);
+
+#[test]
+fn heap_stats() {
+    let before = stats();
+
+    let mut buf = vec![0u8; 1 << 20];
+
+    let during = stats();
+
+    assert!(during.live_bytes >= before.live_bytes + (1 << 20));
+    assert!(during.peak_bytes >= during.live_bytes);
+    assert!(during.allocations > before.allocations);
+    assert!(during.allocated_bytes >= before.allocated_bytes + (1 << 20));
+
+    buf.reserve(2 << 20);
+
+    assert!(stats().reallocations > before.reallocations);
+
+    drop(buf);
+
+    let after = stats();
+
+    assert!(after.frees > before.frees);
+    assert!(after.peak_bytes >= before.live_bytes + (3 << 20));
+}
@@ -0,0 +1,55 @@
+use std::hint::black_box;
+
+use hala_pprof_memory::{
+    proto::gperf::Sample, snapshot_profile, thread_stats, PprofAlloc, Profile,
+};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+const SIZE: usize = 12345;
+
+fn num_label(profile: &Profile, sample: &Sample, key: &str) -> Option<i64> {
+    sample
+        .label
+        .iter()
+        .find(|label| profile.string_table[label.key as usize] == key)
+        .map(|label| label.num)
+}
+
+fn str_label<'a>(profile: &'a Profile, sample: &Sample, key: &str) -> Option<&'a str> {
+    sample
+        .label
+        .iter()
+        .find(|label| profile.string_table[label.key as usize] == key)
+        .map(|label| profile.string_table[label.str as usize].as_str())
+}
+
+#[test]
+fn thread_labels() {
+    let buf = std::thread::Builder::new()
+        .name("labeled".into())
+        .spawn(|| black_box(vec![0u8; SIZE]))
+        .unwrap()
+        .join()
+        .unwrap();
+
+    let id = thread_stats()
+        .into_iter()
+        .find(|stats| stats.name == "labeled")
+        .expect("labeled thread")
+        .id;
+
+    let profile = snapshot_profile();
+
+    let sample = profile
+        .sample
+        .iter()
+        .find(|sample| sample.value[2] > 0 && sample.value[3] == SIZE as i64)
+        .expect("live block sample");
+
+    assert_eq!(num_label(&profile, sample, "thread_id"), Some(id as i64));
+    assert_eq!(str_label(&profile, sample, "thread_name"), Some("labeled"));
+
+    drop(buf);
+}
@@ -0,0 +1,50 @@
+use std::hint::black_box;
+
+use hala_pprof_memory::{thread_stats, PprofAlloc, ThreadStats};
+
+// small blocks are sampled often, with a large count estimate.
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10).with_sample_rate(64);
+
+fn grower() -> ThreadStats {
+    thread_stats()
+        .into_iter()
+        .find(|stats| stats.name == "grower")
+        .expect("grower thread")
+}
+
+/// Allocates a small block and grows it far beyond the sample rate before freeing it.
+#[inline(never)]
+fn grow() {
+    let mut buf = black_box(vec![0u8; 8]);
+
+    buf.reserve_exact(64 * 1024);
+
+    drop(black_box(buf));
+}
+
+#[test]
+fn sampled_reallocs() {
+    let (before, after) = std::thread::Builder::new()
+        .name("grower".into())
+        .spawn(|| {
+            // allocates the thread entry.
+            drop(black_box(vec![0u8; 1024]));
+
+            let before = grower();
+
+            for _ in 0..1000 {
+                grow();
+            }
+
+            (before, grower())
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    // a freed block removes the count it was accounted with, whatever its size.
+    assert_eq!(after.live_blocks, before.live_blocks);
+    assert_eq!(after.live_bytes, before.live_bytes);
+    assert!(after.allocations > before.allocations);
+}
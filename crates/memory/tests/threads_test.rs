@@ -0,0 +1,63 @@
+use hala_pprof_memory::{
+    proto::gperf::Sample, set_report_mode, snapshot_profile, thread_stats, PprofAlloc, Profile,
+    ReportMode,
+};
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10);
+
+/// Returns the string or number of the label `key` of `sample`.
+fn label<'a>(profile: &'a Profile, sample: &Sample, key: &str) -> Option<(&'a str, i64)> {
+    sample
+        .label
+        .iter()
+        .find(|label| profile.string_table[label.key as usize] == key)
+        .map(|label| (profile.string_table[label.str as usize].as_str(), label.num))
+}
+
+#[test]
+fn per_thread_accounting() {
+    let buf = std::thread::Builder::new()
+        .name("producer".into())
+        .spawn(|| vec![0u8; 1 << 20])
+        .unwrap()
+        .join()
+        .unwrap();
+
+    let producer = || {
+        thread_stats()
+            .into_iter()
+            .find(|stats| stats.name == "producer")
+            .expect("producer thread")
+    };
+
+    let before = producer();
+
+    assert!(before.live_bytes >= 1 << 20);
+    assert!(before.allocated_bytes >= 1 << 20);
+
+    set_report_mode(ReportMode::Threads);
+
+    let profile = snapshot_profile();
+
+    // one sample per stack and allocating thread.
+    let groups = profile
+        .sample
+        .iter()
+        .filter(|sample| label(&profile, sample, "thread_name") == Some(("producer", 0)))
+        .collect::<Vec<_>>();
+
+    assert!(groups.iter().all(
+        |group| label(&profile, group, "thread_id").map(|(_, id)| id) == Some(before.id as i64)
+    ));
+
+    assert!(groups.iter().any(|group| group.value[2..4] == [1, 1 << 20]));
+
+    // freed on this thread, accounted to the producer.
+    drop(buf);
+
+    let after = producer();
+
+    assert_eq!(after.live_bytes, before.live_bytes - (1 << 20));
+    assert_eq!(after.allocated_bytes, before.allocated_bytes);
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use std::{fs, hint::black_box};
+
+use hala_pprof_memory::{
+    set_snapshot_config, snapshot, snapshot_profile, FramePointerUnwinder, PprofAlloc,
+    SnapshotConfig, StackUnwinder,
+};
+
+use common::calls;
+
+#[global_allocator]
+static ALLOC: PprofAlloc = PprofAlloc::new(10).with_unwinder(&FramePointerUnwinder);
+
+#[inline(never)]
+fn allocate() -> Vec<u8> {
+    let buf = black_box(vec![0u8; 1024]);
+
+    black_box(buf)
+}
+
+#[inline(never)]
+fn caller() -> Vec<u8> {
+    let buf = allocate();
+
+    black_box(buf)
+}
+
+#[test]
+fn frame_pointer_unwind() {
+    let directory = std::env::temp_dir().join(format!("pprof-unwind-{}", std::process::id()));
+
+    set_snapshot_config(SnapshotConfig::new().with_directory(&directory));
+
+    let mut frames = 0;
+
+    FramePointerUnwinder.unwind(&mut |_| {
+        frames += 1;
+        true
+    });
+
+    assert!(frames > 0);
+
+    let buf = caller();
+
+    let profile = snapshot_profile();
+
+    // the walk goes through the allocator frames up to the allocating function and its caller.
+    assert!(profile.sample.iter().any(|sample| {
+        sample.value[3] == 1024
+            && calls(&profile, sample, "unwind_test::allocate")
+            && calls(&profile, sample, "unwind_test::caller")
+    }));
+
+    snapshot().unwrap();
+
+    drop(buf);
+
+    fs::remove_dir_all(&directory).unwrap();
+}